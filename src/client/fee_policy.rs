@@ -0,0 +1,165 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use anyhow::bail;
+use cln_plugin::{options::Value, Plugin};
+use cln_rpc::primitives::PublicKey;
+
+use crate::{
+    constants::{
+        LSPS1_LSP_MAX_FEE_SAT_OPTION, LSPS1_MAX_FEE_PAID, LSPS1_MAX_FEE_PPM_OPTION,
+        LSPS1_MAX_FEE_SAT_OPTION,
+    },
+    PluginState,
+};
+
+// How much we are willing to pay an LSP for a channel
+pub struct Lsps1FeePolicy {
+    pub max_fee_sat: u64,
+    pub max_fee_ppm: u64,
+    pub lsp_max_fee_sat: HashMap<PublicKey, u64>,
+}
+
+impl Lsps1FeePolicy {
+    pub fn from_options(plugin: &Plugin<Arc<PluginState>>) -> anyhow::Result<Self> {
+        let max_fee_sat = match plugin.option(LSPS1_MAX_FEE_SAT_OPTION) {
+            Some(Value::Integer(sat)) if sat >= 0 => sat as u64,
+            _ => LSPS1_MAX_FEE_PAID,
+        };
+
+        let max_fee_ppm = match plugin.option(LSPS1_MAX_FEE_PPM_OPTION) {
+            Some(Value::Integer(ppm)) if ppm >= 0 => ppm as u64,
+            _ => 0,
+        };
+
+        let lsp_max_fee_sat = match plugin.option(LSPS1_LSP_MAX_FEE_SAT_OPTION) {
+            Some(Value::String(overrides)) => Self::parse_overrides(&overrides)?,
+            _ => HashMap::new(),
+        };
+
+        Ok(Self {
+            max_fee_sat,
+            max_fee_ppm,
+            lsp_max_fee_sat,
+        })
+    }
+
+    // Overrides look like pubkey:sat,pubkey:sat
+    fn parse_overrides(s: &str) -> anyhow::Result<HashMap<PublicKey, u64>> {
+        let mut overrides = HashMap::new();
+
+        for entry in s.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (pubkey, sat) = match entry.split_once(':') {
+                Some(parts) => parts,
+                None => {
+                    bail!("Invalid {} entry: {}", LSPS1_LSP_MAX_FEE_SAT_OPTION, entry)
+                }
+            };
+
+            overrides.insert(PublicKey::from_str(pubkey.trim())?, sat.trim().parse()?);
+        }
+
+        Ok(overrides)
+    }
+
+    // The most we pay for a channel of this size from this LSP
+    // A maxfee passed on the call wins over everything configured
+    pub fn max_fee(&self, lsp: &PublicKey, lsp_balance_sat: u64, maxfee: Option<u64>) -> u64 {
+        if let Some(maxfee) = maxfee {
+            return maxfee;
+        }
+
+        let max_fee_sat = match self.lsp_max_fee_sat.get(lsp) {
+            Some(sat) => *sat,
+            None => self.max_fee_sat,
+        };
+
+        if self.max_fee_ppm == 0 {
+            return max_fee_sat;
+        }
+
+        let max_fee_ppm_sat = lsp_balance_sat.saturating_mul(self.max_fee_ppm) / 1_000_000;
+
+        max_fee_sat.min(max_fee_ppm_sat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LSP: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const OTHER_LSP: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn policy(max_fee_sat: u64, max_fee_ppm: u64, overrides: &str) -> Lsps1FeePolicy {
+        Lsps1FeePolicy {
+            max_fee_sat,
+            max_fee_ppm,
+            lsp_max_fee_sat: Lsps1FeePolicy::parse_overrides(overrides).unwrap(),
+        }
+    }
+
+    fn pubkey(s: &str) -> PublicKey {
+        PublicKey::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parse_overrides() {
+        let overrides =
+            Lsps1FeePolicy::parse_overrides(&format!(" {}:5000 , {}:0,", LSP, OTHER_LSP)).unwrap();
+
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(overrides[&pubkey(LSP)], 5000);
+        assert_eq!(overrides[&pubkey(OTHER_LSP)], 0);
+
+        assert!(Lsps1FeePolicy::parse_overrides("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_overrides_rejects_bad_entries() {
+        assert!(Lsps1FeePolicy::parse_overrides(LSP).is_err());
+        assert!(Lsps1FeePolicy::parse_overrides("02abc:5000").is_err());
+        assert!(Lsps1FeePolicy::parse_overrides(&format!("{}:lots", LSP)).is_err());
+        assert!(Lsps1FeePolicy::parse_overrides(&format!("{}:-1", LSP)).is_err());
+    }
+
+    #[test]
+    fn max_fee_defaults_to_the_sat_cap() {
+        assert_eq!(
+            policy(100000, 0, "").max_fee(&pubkey(LSP), 1000000, None),
+            100000
+        );
+    }
+
+    #[test]
+    fn max_fee_takes_the_lower_of_sat_and_ppm() {
+        let policy = policy(100000, 10000, "");
+
+        // 1% of 1M sat is below the sat cap
+        assert_eq!(policy.max_fee(&pubkey(LSP), 1000000, None), 10000);
+        // 1% of 100M sat is above it
+        assert_eq!(policy.max_fee(&pubkey(LSP), 100000000, None), 100000);
+    }
+
+    #[test]
+    fn max_fee_uses_lsp_overrides() {
+        let policy = policy(100000, 0, &format!("{}:5000", LSP));
+
+        assert_eq!(policy.max_fee(&pubkey(LSP), 1000000, None), 5000);
+        assert_eq!(policy.max_fee(&pubkey(OTHER_LSP), 1000000, None), 100000);
+    }
+
+    #[test]
+    fn max_fee_prefers_the_call_maxfee() {
+        let policy = policy(100000, 10000, &format!("{}:5000", LSP));
+
+        assert_eq!(policy.max_fee(&pubkey(LSP), 1000000, Some(200000)), 200000);
+    }
+
+    #[test]
+    fn max_fee_ppm_does_not_overflow() {
+        assert_eq!(
+            policy(100000, u64::MAX, "").max_fee(&pubkey(LSP), u64::MAX, None),
+            100000
+        );
+    }
+}
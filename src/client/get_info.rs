@@ -1,18 +1,23 @@
 use std::sync::Arc;
 
 use cln_plugin::Plugin;
-use cln_rpc::{
-    model::requests::{ConnectRequest, SendcustommsgRequest},
-    primitives::PublicKey,
-    ClnRpc, Request,
-};
+use cln_rpc::{primitives::PublicKey, ClnRpc};
 
 use crate::{
-    constants::{GetInfoJsonRpcRequest, PluginMethodState, LSPS1_GET_INFO_METHOD, MESSAGE_TYPE},
+    constants::{
+        GetInfoJsonRpcRequest, GetInfoJsonRpcResponseResult, PluginMethodState,
+        LSPS1_GET_INFO_CACHE_SECS, LSPS1_GET_INFO_METHOD,
+    },
     PluginState,
 };
 
-use super::utils::{decode_uri, make_id};
+use super::{
+    list_protocols::Lsps0ListProtocols,
+    utils::{
+        await_response, connect, decode_uri, make_id, register_request, send_message,
+        unix_timestamp,
+    },
+};
 
 pub struct Lsps1GetInfo {
     pub client: ClnRpc,
@@ -21,69 +26,69 @@ pub struct Lsps1GetInfo {
 }
 
 // This method now belongs to an instance of GetInfo and uses its data
-impl<'a> Lsps1GetInfo {
-    pub async fn get_info(&mut self) -> anyhow::Result<()> {
-        let state_ref = self.plugin.state();
-
-        let mut method = state_ref.method.lock().await;
-
-        // Set the state to get info so that
-        // The subscribtion side knows what to do
-        *method = PluginMethodState::GetInfo;
-
+impl Lsps1GetInfo {
+    pub async fn get_info(&mut self) -> anyhow::Result<GetInfoJsonRpcResponseResult> {
         let uri = decode_uri(&self.uri)?;
 
-        Self::connect(&mut self.client, &uri.pubkey, &uri.host, &uri.port).await?;
+        connect(&mut self.client, &uri).await;
 
-        Self::send_get_info_message(&mut self.client, &uri.pubkey).await?;
+        Lsps0ListProtocols::ensure_lsps1_supported(&mut self.client, &self.plugin, &uri.pubkey)
+            .await?;
 
-        Ok(())
+        Self::fetch_info(&mut self.client, &self.plugin, &uri.pubkey).await
     }
 
-    async fn connect(
+    // Reuse what the LSP told us recently instead of asking again
+    pub async fn cached_info(
         client: &mut ClnRpc,
+        plugin: &Plugin<Arc<PluginState>>,
         pubkey: &PublicKey,
-        host: &str,
-        port: &u16,
-    ) -> anyhow::Result<()> {
-        // Ignore errors for connect requests
-        let _ = client
-            .call(Request::Connect(ConnectRequest {
-                id: pubkey.to_string(),
-                host: Some(host.to_string()),
-                port: Some(*port),
-            }))
-            .await;
+    ) -> anyhow::Result<GetInfoJsonRpcResponseResult> {
+        if let Some((fetched_at, info)) = plugin.state().info.lock().await.get(pubkey) {
+            if unix_timestamp().saturating_sub(*fetched_at) < LSPS1_GET_INFO_CACHE_SECS {
+                return Ok(info.clone());
+            }
+        }
+
+        Self::fetch_info(client, plugin, pubkey).await
+    }
 
-        Ok(())
+    async fn fetch_info(
+        client: &mut ClnRpc,
+        plugin: &Plugin<Arc<PluginState>>,
+        pubkey: &PublicKey,
+    ) -> anyhow::Result<GetInfoJsonRpcResponseResult> {
+        let id = make_id();
+        let receiver = register_request(plugin, pubkey, &id, PluginMethodState::GetInfo).await;
+
+        Self::send_get_info_message(client, pubkey, &id).await?;
+
+        let response = await_response(plugin, pubkey, &id, receiver).await?;
+        let info: GetInfoJsonRpcResponseResult = serde_json::from_value(response)?;
+
+        plugin
+            .state()
+            .info
+            .lock()
+            .await
+            .insert(*pubkey, (unix_timestamp(), info.clone()));
+
+        Ok(info)
     }
 
-    async fn send_get_info_message(client: &mut ClnRpc, pubkey: &PublicKey) -> anyhow::Result<()> {
+    async fn send_get_info_message(
+        client: &mut ClnRpc,
+        pubkey: &PublicKey,
+        id: &str,
+    ) -> anyhow::Result<()> {
         let request = GetInfoJsonRpcRequest {
             jsonrpc: "2.0".to_string(),
             method: LSPS1_GET_INFO_METHOD.to_string(),
             params: serde_json::json!({}), // Creating an empty object for params.
-            id: make_id(),
+            id: id.to_string(),
         };
 
-        let json_request = serde_json::to_string(&request)?;
-
-        // Encode the JSON request to hexadecimal
-        let hex_json_request = hex::encode(json_request);
-
-        // Convert the message type 37913 to a 2-byte hexadecimal string
-        let message_type_prefix = MESSAGE_TYPE.to_be_bytes(); // Convert to big-endian bytes
-        let hex_message_type_prefix = hex::encode(message_type_prefix);
-
-        // Prepend the message type prefix to the hex-encoded JSON request
-        let full_hex_message = format!("{}{}", hex_message_type_prefix, hex_json_request);
-
-        client
-            .call(Request::SendCustomMsg(SendcustommsgRequest {
-                msg: full_hex_message,
-                node_id: *pubkey,
-            }))
-            .await?;
+        send_message(client, pubkey, &request).await?;
 
         Ok(())
     }
@@ -1,21 +1,25 @@
 use std::sync::Arc;
 
 use cln_plugin::Plugin;
-use cln_rpc::{
-    model::requests::{ConnectRequest, SendcustommsgRequest},
-    primitives::PublicKey,
-    ClnRpc, Request,
-};
+use cln_rpc::{primitives::PublicKey, ClnRpc};
 
 use crate::{
     constants::{
-        GetOrderJsonRpcRequest, GetOrderJsonRpcRequestParams, PluginMethodState,
-        LSPS1_GET_ORDER_METHOD, MESSAGE_TYPE,
+        CreateOrderJsonRpcResponseResult, GetOrderJsonRpcRequest, GetOrderJsonRpcRequestParams,
+        OrderState, PluginMethodState, LSPS1_GET_ORDER_METHOD, LSPS1_ORDER_COMPLETED_NOTIFICATION,
+        LSPS1_ORDER_FAILED_NOTIFICATION,
     },
+    datastore::save_order,
     PluginState,
 };
 
-use super::utils::{decode_uri, make_id};
+use super::{
+    utils::{
+        await_response, block_height, connect, decode_uri, make_client, make_id, notify_order,
+        register_request, send_message, unix_timestamp,
+    },
+    verify_channel::Lsps1VerifyChannel,
+};
 
 pub struct Lsps1GetOrder {
     pub client: ClnRpc,
@@ -24,40 +28,145 @@ pub struct Lsps1GetOrder {
     pub plugin: Plugin<Arc<PluginState>>,
 }
 
-impl<'a> Lsps1GetOrder {
-    pub async fn get_order(&mut self) -> anyhow::Result<()> {
-        log::info!("inside getorder {}", self.uri);
+impl Lsps1GetOrder {
+    pub async fn get_order(&mut self) -> anyhow::Result<CreateOrderJsonRpcResponseResult> {
+        let uri = decode_uri(&self.uri)?;
 
-        let state_ref = self.plugin.state();
+        connect(&mut self.client, &uri).await;
 
-        let mut method = state_ref.method.lock().await;
-        // Set the state to get order so that
-        // The subscribtion side knows what to do
-        *method = PluginMethodState::GetOrder;
+        let id = make_id();
+        let receiver =
+            register_request(&self.plugin, &uri.pubkey, &id, PluginMethodState::GetOrder).await;
 
-        let uri = decode_uri(&self.uri)?;
+        Self::send_get_order_message(&mut self.client, &uri.pubkey, &self.order_id, &id).await?;
 
-        Self::connect(&mut self.client, &uri.pubkey, &uri.host, &uri.port).await?;
+        let response = await_response(&self.plugin, &uri.pubkey, &id, receiver).await?;
+        let result: CreateOrderJsonRpcResponseResult = serde_json::from_value(response)?;
 
-        Self::send_get_order_message(&mut self.client, &uri.pubkey, &self.order_id).await?;
+        Self::update_stored_order(&mut self.client, &self.plugin, &uri.pubkey, &result).await?;
 
-        Ok(())
+        Ok(result)
     }
 
-    async fn connect(
+    // Keep our copy of the order in sync with what the LSP reports
+    async fn update_stored_order(
         client: &mut ClnRpc,
+        plugin: &Plugin<Arc<PluginState>>,
         pubkey: &PublicKey,
-        host: &str,
-        port: &u16,
+        result: &CreateOrderJsonRpcResponseResult,
     ) -> anyhow::Result<()> {
-        // Ignore errors for connect requests
-        let _ = client
-            .call(Request::Connect(ConnectRequest {
-                id: pubkey.to_string(),
-                host: Some(host.to_string()),
-                port: Some(*port),
-            }))
-            .await;
+        let mut data = plugin.state().data.lock().await;
+
+        let order = data.values_mut().find(|order| {
+            &order.lsp == pubkey
+                && order
+                    .response
+                    .as_ref()
+                    .is_some_and(|response| response.order_id == result.order_id)
+        });
+
+        if let Some(order) = order {
+            let previous_state = order.response.as_ref().map(|r| r.order_state.clone());
+            let had_channel = order.response.as_ref().is_some_and(|r| r.channel.is_some());
+            let previously_confirmed = order
+                .response
+                .as_ref()
+                .and_then(|r| r.payment.onchain_payment.as_ref())
+                .is_some_and(|p| p.confirmed);
+
+            order.response = Some(result.clone());
+            order.updated_at = unix_timestamp();
+
+            if let Some(payment) = &result.payment.onchain_payment {
+                if payment.confirmed && !previously_confirmed {
+                    log::info!(
+                        "LSP confirmed on-chain payment {} for order {}",
+                        payment.outpoint,
+                        result.order_id
+                    );
+                }
+            }
+
+            // The lease runs from when the channel opens
+            if result.channel.is_some() && !had_channel {
+                match block_height(client).await {
+                    Ok(height) => {
+                        order.lease_end_height = Some(height + result.channel_expiry_blocks)
+                    }
+                    Err(e) => log::error!(
+                        "Failed to get the block height for order {}: {}",
+                        result.order_id,
+                        e
+                    ),
+                }
+            }
+
+            // Don't take the LSP's word for the order or the channel it opened,
+            // check both against what we asked for
+            let mut misreported: Vec<String> =
+                Lsps1VerifyChannel::check_reply(&order.request.params, result)
+                    .into_iter()
+                    .collect();
+
+            // A failed check only gets logged, the new state is stored either way
+            let mut checked = true;
+
+            if result.channel.is_some() {
+                let verified = match make_client(plugin).await {
+                    Ok(client) => {
+                        Lsps1VerifyChannel {
+                            client,
+                            lsp: *pubkey,
+                            request: order.request.params.clone(),
+                            order: result.clone(),
+                        }
+                        .verify_channel()
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+
+                match verified {
+                    Ok(reason) => misreported.extend(reason),
+                    Err(e) => {
+                        checked = false;
+
+                        log::error!(
+                            "Failed to verify the channel for order {}: {}",
+                            result.order_id,
+                            e
+                        )
+                    }
+                }
+            }
+
+            for reason in &misreported {
+                log::warn!("LSP misreported order {}: {}", result.order_id, reason);
+            }
+
+            // Keep what we found last time if we couldn't check the channel now
+            if checked || !misreported.is_empty() {
+                order.lsp_misreported = if misreported.is_empty() {
+                    None
+                } else {
+                    Some(misreported.join("; "))
+                };
+            }
+
+            save_order(client, order).await?;
+
+            if previous_state.as_ref() != Some(&result.order_state) {
+                match result.order_state {
+                    OrderState::Completed => {
+                        notify_order(plugin, LSPS1_ORDER_COMPLETED_NOTIFICATION, order).await
+                    }
+                    OrderState::Failed => {
+                        notify_order(plugin, LSPS1_ORDER_FAILED_NOTIFICATION, order).await
+                    }
+                    OrderState::Created => {}
+                }
+            }
+        }
 
         Ok(())
     }
@@ -66,6 +175,7 @@ impl<'a> Lsps1GetOrder {
         client: &mut ClnRpc,
         pubkey: &PublicKey,
         order_id: &str,
+        id: &str,
     ) -> anyhow::Result<()> {
         let params = GetOrderJsonRpcRequestParams {
             order_id: order_id.to_string(),
@@ -75,27 +185,10 @@ impl<'a> Lsps1GetOrder {
             jsonrpc: "2.0".to_string(),
             method: LSPS1_GET_ORDER_METHOD.to_string(),
             params,
-            id: make_id(),
+            id: id.to_string(),
         };
 
-        let json_request = serde_json::to_string(&request)?;
-
-        // Encode the JSON request to hexadecimal
-        let hex_json_request = hex::encode(json_request);
-
-        // Convert the message type 37913 to a 2-byte hexadecimal string
-        let message_type_prefix = MESSAGE_TYPE.to_be_bytes(); // Convert to big-endian bytes
-        let hex_message_type_prefix = hex::encode(message_type_prefix);
-
-        // Prepend the message type prefix to the hex-encoded JSON request
-        let full_hex_message = format!("{}{}", hex_message_type_prefix, hex_json_request);
-
-        client
-            .call(Request::SendCustomMsg(SendcustommsgRequest {
-                msg: full_hex_message,
-                node_id: *pubkey,
-            }))
-            .await?;
+        send_message(client, pubkey, &request).await?;
 
         Ok(())
     }
@@ -0,0 +1,94 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::bail;
+use cln_plugin::Plugin;
+use cln_rpc::primitives::PublicKey;
+use serde_json::json;
+
+use crate::{
+    constants::{Lsps1Order, OrderState},
+    PluginState,
+};
+
+pub struct Lsps1ListOrders {
+    pub lsp: Option<String>,
+    pub state: Option<String>,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ListOrders {
+    pub async fn list_orders(&self) -> anyhow::Result<serde_json::Value> {
+        let lsp = match &self.lsp {
+            Some(lsp) => Some(PublicKey::from_str(lsp)?),
+            None => None,
+        };
+
+        let state = match &self.state {
+            Some(state) => match state.to_lowercase().as_str() {
+                "created" => Some(OrderState::Created),
+                "completed" => Some(OrderState::Completed),
+                "failed" => Some(OrderState::Failed),
+                _ => {
+                    bail!("Invalid state, must be one of (created, completed, failed)")
+                }
+            },
+            None => None,
+        };
+
+        let data = self.plugin.state().data.lock().await;
+
+        let mut orders: Vec<&Lsps1Order> = data
+            .values()
+            .filter(|order| lsp.is_none_or(|lsp| order.lsp == lsp))
+            .filter(|order| {
+                state.as_ref().is_none_or(|state| {
+                    order
+                        .response
+                        .as_ref()
+                        .is_some_and(|response| &response.order_state == state)
+                })
+            })
+            .collect();
+
+        orders.sort_by_key(|order| order.created_at);
+
+        let orders: Vec<serde_json::Value> = orders.into_iter().map(Self::summarize).collect();
+
+        Ok(json!({ "orders": orders }))
+    }
+
+    fn summarize(order: &Lsps1Order) -> serde_json::Value {
+        let response = order.response.as_ref();
+
+        json!({
+            "id": order.request.id,
+            "lsp": order.lsp.to_string(),
+            "order_id": response.map(|r| &r.order_id),
+            "order_state": response.map(|r| &r.order_state),
+            "lsp_balance_sat": order.request.params.lsp_balance_sat,
+            "client_balance_sat": order.request.params.client_balance_sat,
+            "channel_expiry_blocks": order.request.params.channel_expiry_blocks,
+            "lease_end_height": order.lease_end_height,
+            "token": order.request.params.token,
+            "announce_channel": order.request.params.announce_channel,
+            "payment_state": response.map(|r| &r.payment.state),
+            "fee_total_sat": response.map(|r| &r.payment.fee_total_sat),
+            "order_total_sat": response.map(|r| &r.payment.order_total_sat),
+            "funding_outpoint": response
+                .and_then(|r| r.channel.as_ref())
+                .map(|c| &c.funding_outpoint),
+            "payment_method": order.payment_method,
+            "onchain_txid": order.onchain_txid,
+            "onchain_confirmed": response
+                .and_then(|r| r.payment.onchain_payment.as_ref())
+                .map(|p| p.confirmed),
+            "payment_status": order.payment_status,
+            "payment_error": order.payment_error,
+            "refund_status": order.refund_status,
+            "error": order.error,
+            "lsp_misreported": order.lsp_misreported,
+            "created_at": order.created_at,
+            "updated_at": order.updated_at,
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::Plugin;
+use cln_rpc::{primitives::PublicKey, ClnRpc};
+
+use crate::{
+    constants::{
+        ListProtocolsJsonRpcRequest, ListProtocolsJsonRpcResponseResult, PluginMethodState,
+        LSPS0_LIST_PROTOCOLS_METHOD, LSPS1_PROTOCOL,
+    },
+    PluginState,
+};
+
+use super::utils::{await_response, connect, decode_uri, make_id, register_request, send_message};
+
+pub struct Lsps0ListProtocols {
+    pub client: ClnRpc,
+    pub uri: String,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps0ListProtocols {
+    pub async fn list_protocols(&mut self) -> anyhow::Result<ListProtocolsJsonRpcResponseResult> {
+        let uri = decode_uri(&self.uri)?;
+
+        connect(&mut self.client, &uri).await;
+
+        Self::send_list_protocols_message(&mut self.client, &self.plugin, &uri.pubkey).await
+    }
+
+    // Make sure the peer sells channels before we talk lsps1 to it
+    pub async fn ensure_lsps1_supported(
+        client: &mut ClnRpc,
+        plugin: &Plugin<Arc<PluginState>>,
+        pubkey: &PublicKey,
+    ) -> anyhow::Result<()> {
+        let result = Self::send_list_protocols_message(client, plugin, pubkey).await?;
+
+        if !result.protocols.contains(&LSPS1_PROTOCOL) {
+            bail!(
+                "Peer {} does not support LSPS1, supported protocols: {:?}",
+                pubkey,
+                result.protocols
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn send_list_protocols_message(
+        client: &mut ClnRpc,
+        plugin: &Plugin<Arc<PluginState>>,
+        pubkey: &PublicKey,
+    ) -> anyhow::Result<ListProtocolsJsonRpcResponseResult> {
+        let id = make_id();
+
+        let request = ListProtocolsJsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: LSPS0_LIST_PROTOCOLS_METHOD.to_string(),
+            params: serde_json::json!({}), // Creating an empty object for params.
+            id: id.clone(),
+        };
+
+        let receiver =
+            register_request(plugin, pubkey, &id, PluginMethodState::ListProtocols).await;
+
+        send_message(client, pubkey, &request).await?;
+
+        let response = await_response(plugin, pubkey, &id, receiver).await?;
+
+        Ok(serde_json::from_value(response)?)
+    }
+}
@@ -2,17 +2,24 @@ use std::{path::Path, sync::Arc};
 
 use anyhow::bail;
 use cln_plugin::{Error, Plugin};
-use cln_rpc::ClnRpc;
+use cln_rpc::{primitives::Feerate, ClnRpc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
     client::{get_info::Lsps1GetInfo, send_order::Lsps1SendOrder},
-    constants::PluginMethodState,
+    constants::{
+        Lsps1PaymentMethod, LSPS1_CHANNEL_EXPIRY_BLOCKS_OPTION,
+        LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS, LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE,
+        LSPS1_CREATE_ORDER_TOKEN,
+    },
     PluginState,
 };
 
-use super::get_order::Lsps1GetOrder;
+use super::{
+    get_order::Lsps1GetOrder, list_orders::Lsps1ListOrders, list_protocols::Lsps0ListProtocols,
+    pay_order::Lsps1PayOrder,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -22,6 +29,9 @@ enum BuyRequestTypes {
     Dryrun,
     GetInfo,
     GetOrder,
+    ListOrders,
+    ListProtocols,
+    PayOrder,
 }
 
 fn str_to_buy_request_type(s: &str) -> Option<BuyRequestTypes> {
@@ -29,8 +39,12 @@ fn str_to_buy_request_type(s: &str) -> Option<BuyRequestTypes> {
         // Ensure matching is case-insensitive
         "help" => Some(BuyRequestTypes::Help),
         "buy" => Some(BuyRequestTypes::Buy),
+        "dryrun" => Some(BuyRequestTypes::Dryrun),
         "getinfo" => Some(BuyRequestTypes::GetInfo),
         "getorder" => Some(BuyRequestTypes::GetOrder),
+        "listorders" => Some(BuyRequestTypes::ListOrders),
+        "listprotocols" => Some(BuyRequestTypes::ListProtocols),
+        "payorder" => Some(BuyRequestTypes::PayOrder),
         _ => None,
     }
 }
@@ -43,29 +57,27 @@ pub async fn lsps1_client(
     let socket_path = Path::new(&conf.lightning_dir).join(&conf.rpc_file);
     let client = ClnRpc::new(socket_path).await?;
 
-    // Set the method to blank
-    // Its later updated based on the method
-    let state_ref = p.state().clone();
-
-    let mut method = state_ref.method.lock().await;
-
-    *method = PluginMethodState::None;
-    std::mem::drop(method);
-
     match v["method"].as_str().and_then(str_to_buy_request_type) {
-        Some(BuyRequestTypes::Help) => {
-            return Ok(json!({
-                "cli_params": {
-                    "method": "Method can be one of the following: (help, buy, getinfo, getorder)",
-                    "amount": "<number> enter the channel size you want to buy",
-                    "blocks": "<number> enter the number of blocks you want to wait for the channel to be confirmed",
-                    "type": "<private/public> the type of channel you want to buy",
-                    "orderid": "<orderid> returns the status of the order",
-                    "uri": "<uri> pubkey@host:port",
-                }
-            }));
-        }
-        Some(BuyRequestTypes::Buy) => {
+        Some(BuyRequestTypes::Help) => Ok(json!({
+            "cli_params": {
+                "method": "Method can be one of the following: (help, buy, dryrun, getinfo, getorder, payorder, listorders, listprotocols)",
+                "amount": "<number> enter the channel size you want to buy",
+                "blocks": "<number> enter the number of blocks you want to wait for the channel to be confirmed",
+                "clientbalance": "<number> sats the LSP pushes to our side of the channel, paid on top of the fee, defaults to 0 (buy)",
+                "expiry": "<number> blocks the LSP has to keep the channel open, defaults to lsps1-channel-expiry-blocks (buy)",
+                "type": "<private/public> the type of channel you want to buy",
+                "maxfee": "<number> the most you want to pay in sats, overrides the configured fee limits (buy)",
+                "payment": "<lightning/onchain> how to pay for the order, defaults to lightning (buy)",
+                "feerate": "<feerate> feerate for on-chain payments, e.g. normal, urgent or 3000perkw (buy)",
+                "orderid": "<orderid> returns the status of the order, or the order to pay (payorder)",
+                "token": "<token> a promo or coupon code from the LSP (buy)",
+                "refundaddress": "<address/bech32/p2tr/none> where the LSP refunds on-chain payments, a new address of that type from our wallet, or none to leave it out, defaults to bech32 (buy)",
+                "uri": "<uri> pubkey@host:port",
+                "lsp": "<pubkey> only list orders placed with this LSP (listorders)",
+                "state": "<created/completed/failed> only list orders in this state (listorders)",
+            }
+        })),
+        Some(request_type @ (BuyRequestTypes::Buy | BuyRequestTypes::Dryrun)) => {
             let amount = match v["amount"].as_u64() {
                 Some(amount) => amount,
                 None => {
@@ -102,9 +114,96 @@ pub async fn lsps1_client(
                 }
             };
 
-            Lsps1SendOrder {
+            let client_balance = match &v["clientbalance"] {
+                serde_json::Value::Null => LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE,
+                client_balance => match client_balance.as_u64() {
+                    Some(client_balance) => client_balance,
+                    None => {
+                        bail!("Invalid clientbalance")
+                    }
+                },
+            };
+
+            let default_expiry = match p.option(LSPS1_CHANNEL_EXPIRY_BLOCKS_OPTION) {
+                Some(cln_plugin::options::Value::Integer(blocks)) if blocks > 0 => blocks as u32,
+                _ => LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS,
+            };
+
+            let expiry = match &v["expiry"] {
+                serde_json::Value::Null => default_expiry,
+                expiry => match expiry.as_u64().and_then(|e| u32::try_from(e).ok()) {
+                    Some(expiry) if expiry > 0 => expiry,
+                    _ => {
+                        bail!("Invalid expiry")
+                    }
+                },
+            };
+
+            let token = match &v["token"] {
+                serde_json::Value::Null => LSPS1_CREATE_ORDER_TOKEN.to_string(),
+                token => match token.as_str() {
+                    Some(token) => token.to_string(),
+                    None => {
+                        bail!("Invalid token")
+                    }
+                },
+            };
+
+            let refund_address = match &v["refundaddress"] {
+                serde_json::Value::Null => None,
+                refund_address => match refund_address.as_str() {
+                    Some(refund_address) => Some(refund_address.to_string()),
+                    None => {
+                        bail!("Invalid refundaddress")
+                    }
+                },
+            };
+
+            let max_fee = match &v["maxfee"] {
+                serde_json::Value::Null => None,
+                maxfee => match maxfee.as_u64() {
+                    Some(maxfee) => Some(maxfee),
+                    None => {
+                        bail!("Invalid maxfee")
+                    }
+                },
+            };
+
+            let payment_method = match v["payment"].as_str() {
+                None => Lsps1PaymentMethod::Lightning,
+                Some(t) => match t.to_lowercase().as_str() {
+                    "lightning" => Lsps1PaymentMethod::Lightning,
+                    "onchain" => Lsps1PaymentMethod::Onchain,
+                    _ => {
+                        bail!("Invalid payment, must be one of (lightning, onchain)")
+                    }
+                },
+            };
+
+            // Catch a bad feerate now rather than when we pay
+            let feerate = match &v["feerate"] {
+                serde_json::Value::Null => None,
+                feerate => match feerate.as_str() {
+                    Some(feerate) if Feerate::try_from(feerate).is_ok() => {
+                        Some(feerate.to_string())
+                    }
+                    _ => {
+                        bail!("Invalid feerate")
+                    }
+                },
+            };
+
+            let order = Lsps1SendOrder {
                 amount,
+                client_balance,
                 blocks,
+                expiry,
+                token,
+                refund_address,
+                max_fee,
+                payment_method,
+                feerate,
+                dry_run: matches!(request_type, BuyRequestTypes::Dryrun),
                 client,
                 is_public_channel,
                 plugin: p,
@@ -113,9 +212,7 @@ pub async fn lsps1_client(
             .send_order()
             .await?;
 
-            return Ok(json!({
-                "result": "success"
-            }));
+            Ok(order)
         }
         Some(BuyRequestTypes::GetInfo) => {
             let uri_str = match v["uri"].as_str() {
@@ -124,7 +221,7 @@ pub async fn lsps1_client(
                     bail!("Invalid URI")
                 }
             };
-            Lsps1GetInfo {
+            let info = Lsps1GetInfo {
                 client,
                 uri: uri_str.to_string(),
                 plugin: p,
@@ -132,9 +229,7 @@ pub async fn lsps1_client(
             .get_info()
             .await?;
 
-            return Ok(json!({
-                "result": "success"
-            }));
+            Ok(serde_json::to_value(info)?)
         }
         Some(BuyRequestTypes::GetOrder) => {
             let order_id = match v["orderid"].as_str() {
@@ -151,7 +246,7 @@ pub async fn lsps1_client(
                 }
             };
 
-            Lsps1GetOrder {
+            let order = Lsps1GetOrder {
                 client,
                 uri: uri_str.to_string(),
                 order_id: order_id.to_string(),
@@ -160,15 +255,54 @@ pub async fn lsps1_client(
             .get_order()
             .await?;
 
-            return Ok(json!({
-                "result": "success"
-            }));
+            Ok(serde_json::to_value(order)?)
         }
-        _ => {
-            return Ok(json!({
-                "result": "error",
-                "message": "Invalid request"
-            }));
+        Some(BuyRequestTypes::PayOrder) => {
+            let order_id = match v["orderid"].as_str() {
+                Some(order_id) => order_id,
+                None => {
+                    bail!("Invalid orderid")
+                }
+            };
+
+            Lsps1PayOrder {
+                client,
+                order_id: order_id.to_string(),
+                plugin: p,
+            }
+            .pay_order()
+            .await
+        }
+        Some(BuyRequestTypes::ListOrders) => {
+            Lsps1ListOrders {
+                lsp: v["lsp"].as_str().map(|s| s.to_string()),
+                state: v["state"].as_str().map(|s| s.to_string()),
+                plugin: p,
+            }
+            .list_orders()
+            .await
+        }
+        Some(BuyRequestTypes::ListProtocols) => {
+            let uri_str = match v["uri"].as_str() {
+                Some(uri) => uri,
+                None => {
+                    bail!("Invalid URI")
+                }
+            };
+
+            let protocols = Lsps0ListProtocols {
+                client,
+                uri: uri_str.to_string(),
+                plugin: p,
+            }
+            .list_protocols()
+            .await?;
+
+            Ok(serde_json::to_value(protocols)?)
         }
+        _ => Ok(json!({
+            "result": "error",
+            "message": "Invalid request"
+        })),
     }
 }
@@ -1,6 +1,13 @@
+pub mod fee_policy;
 pub mod get_info;
 pub mod get_order;
+pub mod list_orders;
+pub mod list_protocols;
 pub mod lsps1_client;
+pub mod pay_order;
+pub mod poll_orders;
 pub mod send_order;
+pub mod track_refunds;
 pub mod utils;
 pub mod validate_and_pay;
+pub mod verify_channel;
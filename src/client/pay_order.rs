@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::{options::Value, Plugin};
+use cln_rpc::ClnRpc;
+
+use crate::{
+    constants::{
+        CreateOrderJsonRpcResponse, Lsps1PaymentStatus, LSPS1_AUTO_PAY_MAX_SAT_OPTION,
+        LSPS1_CONFIRM_PAYMENT_OPTION, LSPS1_ORDER_FAILED_NOTIFICATION,
+        LSPS1_ORDER_PAID_NOTIFICATION,
+    },
+    datastore::save_order,
+    PluginState,
+};
+
+use super::{
+    get_order::Lsps1GetOrder,
+    utils::{make_client, notify_order, unix_timestamp},
+    validate_and_pay::Lsps1ValidateAndPay,
+};
+
+pub struct Lsps1PayOrder {
+    pub client: ClnRpc,
+    pub order_id: String,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1PayOrder {
+    // Orders above the auto pay limit wait for payorder when confirmation is turned on
+    pub fn needs_confirmation(plugin: &Plugin<Arc<PluginState>>, order_total_sat: u64) -> bool {
+        let confirm = matches!(
+            plugin.option(LSPS1_CONFIRM_PAYMENT_OPTION),
+            Some(Value::Boolean(true))
+        );
+
+        let auto_pay_max_sat = match plugin.option(LSPS1_AUTO_PAY_MAX_SAT_OPTION) {
+            Some(Value::Integer(sat)) if sat > 0 => sat as u64,
+            _ => 0,
+        };
+
+        confirm && order_total_sat > auto_pay_max_sat
+    }
+
+    pub async fn pay_order(&mut self) -> anyhow::Result<serde_json::Value> {
+        let (id, uri, request, max_fee_sat, payment_method, feerate, min_onchain_payment_sat) = {
+            let mut data = self.plugin.state().data.lock().await;
+
+            let order = data.values_mut().find(|order| {
+                order
+                    .response
+                    .as_ref()
+                    .is_some_and(|response| response.order_id == self.order_id)
+            });
+
+            match order {
+                Some(order) if order.payment_status == Lsps1PaymentStatus::AwaitingConfirmation => {
+                    // Claim the order so a second payorder can't pay it twice
+                    order.payment_status = Lsps1PaymentStatus::Pending;
+
+                    (
+                        order.request.id.clone(),
+                        order.uri.clone(),
+                        order.request.clone(),
+                        order.max_fee_sat,
+                        order.payment_method,
+                        order.feerate.clone(),
+                        order.min_onchain_payment_sat,
+                    )
+                }
+                Some(order) => {
+                    bail!(
+                        "Order {} is not awaiting confirmation, payment status is {:?}",
+                        self.order_id,
+                        order.payment_status
+                    )
+                }
+                None => {
+                    bail!("Order {} not found", self.order_id)
+                }
+            }
+        };
+
+        // The order might have changed since we saw it, so ask the LSP again
+        let refreshed = match make_client(&self.plugin).await {
+            Ok(client) => {
+                Lsps1GetOrder {
+                    client,
+                    uri,
+                    order_id: self.order_id.clone(),
+                    plugin: self.plugin.clone(),
+                }
+                .get_order()
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let result = match refreshed {
+            Ok(result) => result,
+            Err(e) => {
+                // Nothing was paid, so let the user try again
+                if let Some(order) = self.plugin.state().data.lock().await.get_mut(&id) {
+                    order.payment_status = Lsps1PaymentStatus::AwaitingConfirmation;
+                }
+
+                return Err(e);
+            }
+        };
+
+        let mut validator = Lsps1ValidateAndPay {
+            order: request,
+            max_fee_sat,
+            payment_method,
+            feerate,
+            min_onchain_payment_sat,
+            network: self.plugin.configuration().network,
+            client: make_client(&self.plugin).await?,
+            order_response_payload: CreateOrderJsonRpcResponse {
+                id: id.clone(),
+                jsonrpc: "2.0".to_string(),
+                result: result.clone(),
+            },
+        };
+
+        let res = validator.validate_and_pay().await;
+
+        let mut data = self.plugin.state().data.lock().await;
+
+        if let Some(order) = data.get_mut(&id) {
+            order.updated_at = unix_timestamp();
+
+            match &res {
+                Ok(txid) => {
+                    order.payment_status = Lsps1PaymentStatus::Paid;
+                    order.onchain_txid = txid.clone();
+                }
+                Err(e) => {
+                    order.payment_status = Lsps1PaymentStatus::Failed;
+                    order.payment_error = Some(e.to_string());
+                }
+            }
+
+            save_order(&mut self.client, order).await?;
+
+            match &res {
+                Ok(_) => notify_order(&self.plugin, LSPS1_ORDER_PAID_NOTIFICATION, order).await,
+                Err(_) => notify_order(&self.plugin, LSPS1_ORDER_FAILED_NOTIFICATION, order).await,
+            }
+        }
+
+        match res {
+            Ok(_) => {
+                log::info!("Order {} confirmed and paid", self.order_id);
+
+                Ok(serde_json::to_value(&result)?)
+            }
+            Err(e) => {
+                bail!(
+                    "Order {} validation and payment failed: {}",
+                    self.order_id,
+                    e
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::{sync::Arc, time::Duration};
+
+use cln_plugin::Plugin;
+
+use crate::{
+    constants::{
+        OrderState, LSPS1_LEASE_EXPIRING_NOTIFICATION, LSPS1_LEASE_WARNING_BLOCKS,
+        LSPS1_LEASE_WARNING_BLOCKS_OPTION, LSPS1_POLL_INTERVAL_OPTION, LSPS1_POLL_INTERVAL_SECS,
+    },
+    datastore::save_order,
+    PluginState,
+};
+
+use super::{
+    get_order::Lsps1GetOrder,
+    track_refunds::Lsps1TrackRefunds,
+    utils::{block_height, make_client, notify_order, unix_timestamp},
+};
+
+pub struct Lsps1PollOrders {
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1PollOrders {
+    // Keep asking the LSPs about every open order until it's done
+    pub async fn run(&self) {
+        let interval = match self.plugin.option(LSPS1_POLL_INTERVAL_OPTION) {
+            Some(cln_plugin::options::Value::Integer(secs)) => secs,
+            _ => LSPS1_POLL_INTERVAL_SECS as i64,
+        };
+
+        if interval <= 0 {
+            log::info!("Order polling is disabled");
+            return;
+        }
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval as u64)).await;
+
+            if let Err(e) = self.poll_orders().await {
+                log::error!("Polling orders failed: {}", e);
+            }
+
+            if let Err(e) = self.check_leases().await {
+                log::error!("Checking leases failed: {}", e);
+            }
+
+            if let Err(e) = self.track_refunds().await {
+                log::error!("Tracking refunds failed: {}", e);
+            }
+        }
+    }
+
+    async fn poll_orders(&self) -> anyhow::Result<()> {
+        // Collect what needs polling first so we don't hold the lock while we wait on LSPs
+        let orders: Vec<(String, String)> = {
+            let data = self.plugin.state().data.lock().await;
+
+            data.values()
+                .filter(|order| !order.is_terminal())
+                .filter_map(|order| {
+                    order
+                        .response
+                        .as_ref()
+                        .map(|response| (order.uri.clone(), response.order_id.clone()))
+                })
+                .collect()
+        };
+
+        for (uri, order_id) in orders {
+            let res = Lsps1GetOrder {
+                client: make_client(&self.plugin).await?,
+                uri,
+                order_id: order_id.clone(),
+                plugin: self.plugin.clone(),
+            }
+            .get_order()
+            .await;
+
+            match res {
+                Ok(order) => match order.order_state {
+                    OrderState::Created => {
+                        log::debug!("Order {} is still {:?}", order_id, order.payment.state);
+                    }
+                    _ => {
+                        log::info!(
+                            "Order {} finished with state {:?}",
+                            order_id,
+                            order.order_state
+                        );
+                    }
+                },
+                Err(e) => {
+                    log::warn!("Failed to poll order {}: {}", order_id, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn track_refunds(&self) -> anyhow::Result<()> {
+        Lsps1TrackRefunds {
+            client: make_client(&self.plugin).await?,
+            plugin: self.plugin.clone(),
+        }
+        .track_refunds()
+        .await
+    }
+
+    // Warn once when a channel we bought is about to reach the end of its lease
+    async fn check_leases(&self) -> anyhow::Result<()> {
+        let warning_blocks = match self.plugin.option(LSPS1_LEASE_WARNING_BLOCKS_OPTION) {
+            Some(cln_plugin::options::Value::Integer(blocks)) if blocks >= 0 => blocks as u32,
+            _ => LSPS1_LEASE_WARNING_BLOCKS,
+        };
+
+        let mut client = make_client(&self.plugin).await?;
+        let height = block_height(&mut client).await?;
+
+        let mut data = self.plugin.state().data.lock().await;
+
+        for order in data.values_mut() {
+            let completed = order
+                .response
+                .as_ref()
+                .is_some_and(|r| r.order_state == OrderState::Completed);
+
+            let lease_end_height = match order.lease_end_height {
+                Some(end) if completed && !order.lease_warned => end,
+                _ => continue,
+            };
+
+            if height + warning_blocks < lease_end_height {
+                continue;
+            }
+
+            log::warn!(
+                "Lease for order {} ends at block {}, current block is {}",
+                order.request.id,
+                lease_end_height,
+                height
+            );
+
+            order.lease_warned = true;
+            order.updated_at = unix_timestamp();
+
+            save_order(&mut client, order).await?;
+            notify_order(&self.plugin, LSPS1_LEASE_EXPIRING_NOTIFICATION, order).await;
+        }
+
+        Ok(())
+    }
+}
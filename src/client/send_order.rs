@@ -1,150 +1,286 @@
 use std::sync::Arc;
 
+use anyhow::bail;
 use cln_plugin::Plugin;
 use cln_rpc::{
-    model::requests::{ConnectRequest, NewaddrRequest, SendcustommsgRequest},
+    model::requests::{NewaddrAddresstype, NewaddrRequest},
     primitives::PublicKey,
     ClnRpc, Request, Response,
 };
 
 use crate::{
     constants::{
-        CreateOrderJsonRpcRequest, CreateOrderJsonRpcRequestParams, PluginMethodState,
-        LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS, LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE,
-        LSPS1_CREATE_ORDER_METHOD, LSPS1_CREATE_ORDER_TOKEN, MESSAGE_TYPE,
+        CreateOrderJsonRpcRequest, CreateOrderJsonRpcRequestParams, GetInfoJsonRpcResponseOptions,
+        Lsps1Order, Lsps1PaymentMethod, Lsps1PaymentStatus, PluginMethodState,
+        LSPS1_CREATE_ORDER_METHOD,
     },
+    datastore::save_order,
     PluginState,
 };
 
-use super::utils::{decode_uri, make_id};
+use super::{
+    fee_policy::Lsps1FeePolicy,
+    get_info::Lsps1GetInfo,
+    list_protocols::Lsps0ListProtocols,
+    utils::{
+        await_response, block_height, check_address, connect, decode_uri, make_id,
+        register_request, send_message, unix_timestamp,
+    },
+};
 
 pub struct Lsps1SendOrder {
     pub client: ClnRpc,
     pub is_public_channel: bool,
     pub amount: u64,
+    pub client_balance: u64,
     pub blocks: u64,
+    pub expiry: u32,
+    pub token: String,
+    pub refund_address: Option<String>,
+    pub max_fee: Option<u64>,
+    pub dry_run: bool,
+    pub payment_method: Lsps1PaymentMethod,
+    pub feerate: Option<String>,
     pub uri: String,
     pub plugin: Plugin<Arc<PluginState>>,
 }
 
 impl Lsps1SendOrder {
-    pub async fn send_order(&mut self) -> anyhow::Result<()> {
-        let state_ref = self.plugin.state();
+    pub async fn send_order(&mut self) -> anyhow::Result<serde_json::Value> {
+        let uri = decode_uri(&self.uri)?;
 
-        let mut method = state_ref.method.lock().await;
-        // Set the state to get info so that
-        // The subscribtion side knows what to do
-        *method = PluginMethodState::SendOrder;
+        connect(&mut self.client, &uri).await;
 
-        let uri = decode_uri(&self.uri)?;
+        Lsps0ListProtocols::ensure_lsps1_supported(&mut self.client, &self.plugin, &uri.pubkey)
+            .await?;
 
-        Self::connect(&mut self.client, &uri.pubkey, &uri.host, &uri.port).await?;
+        // Catch anything the LSP would refuse before we send it
+        let info = Lsps1GetInfo::cached_info(&mut self.client, &self.plugin, &uri.pubkey).await?;
+        self.check_options(&info.options)?;
 
-        let refund_address = Self::make_refund_address(&mut self.client).await?;
+        let refund_address = self.make_refund_address().await?;
 
-        Self::send_get_order_message(
-            &mut self.client,
+        let max_fee_sat = Lsps1FeePolicy::from_options(&self.plugin)?.max_fee(
+            &uri.pubkey,
             self.amount,
-            self.blocks as u32,
+            self.max_fee,
+        );
+
+        let id = make_id();
+        let receiver =
+            register_request(&self.plugin, &uri.pubkey, &id, PluginMethodState::SendOrder).await;
+
+        let min_onchain_payment_sat = info.options.min_onchain_payment_size_sat.map(u64::from);
+
+        self.send_get_order_message(
             &uri.pubkey,
             &refund_address,
-            &self.plugin,
-            self.is_public_channel,
+            max_fee_sat,
+            min_onchain_payment_sat,
+            &id,
         )
         .await?;
 
-        Ok(())
+        // The subscription side validates and pays the order
+        // before handing us the response, or a quote for dry runs
+        await_response(&self.plugin, &uri.pubkey, &id, receiver).await
     }
 
-    async fn connect(
-        client: &mut ClnRpc,
-        pubkey: &PublicKey,
-        host: &str,
-        port: &u16,
-    ) -> anyhow::Result<()> {
-        // Ignore errors for connect requests
-        let _ = client
-            .call(Request::Connect(ConnectRequest {
-                id: pubkey.to_string(),
-                host: Some(host.to_string()),
-                port: Some(*port),
-            }))
-            .await;
+    fn check_options(&self, options: &GetInfoJsonRpcResponseOptions) -> anyhow::Result<()> {
+        check_range(
+            "amount",
+            self.amount,
+            &options.min_initial_lsp_balance_sat,
+            &options.max_initial_lsp_balance_sat,
+        )?;
+
+        check_range(
+            "client balance",
+            self.client_balance,
+            &options.min_initial_client_balance_sat,
+            &options.max_initial_client_balance_sat,
+        )?;
+
+        check_range(
+            "channel size",
+            self.amount + self.client_balance,
+            &options.min_channel_balance_sat,
+            &options.max_channel_balance_sat,
+        )?;
+
+        if self.expiry > options.max_channel_expiry_blocks {
+            bail!(
+                "Channel expiry of {} blocks is above the LSP maximum of {} blocks",
+                self.expiry,
+                options.max_channel_expiry_blocks
+            );
+        }
+
+        // The minimum is checked against the order total once we know it
+        if self.payment_method == Lsps1PaymentMethod::Onchain
+            && options.min_onchain_payment_size_sat.is_none()
+        {
+            bail!("LSP does not accept on-chain payments");
+        }
 
         Ok(())
     }
 
-    async fn make_refund_address(client: &mut ClnRpc) -> anyhow::Result<String> {
-        let res = client
+    // Use the address we were given, or get one from our wallet, or skip it with none
+    async fn make_refund_address(&mut self) -> anyhow::Result<Option<String>> {
+        let addresstype = match self.refund_address.as_deref() {
+            None | Some("bech32") => NewaddrAddresstype::BECH32,
+            Some("p2tr") => NewaddrAddresstype::P2TR,
+            Some("none") => return Ok(None),
+            Some(address) => {
+                check_address(address, &self.plugin.configuration().network)?;
+
+                return Ok(Some(address.to_string()));
+            }
+        };
+
+        let res = self
+            .client
             .call(Request::NewAddr(NewaddrRequest {
-                addresstype: Some(cln_rpc::model::requests::NewaddrAddresstype::BECH32),
+                addresstype: Some(addresstype),
             }))
             .await?;
 
-        let address = match res {
-            Response::NewAddr(a) => a.bech32.expect("No bech32 address generated"),
+        let address = match (res, addresstype) {
+            (Response::NewAddr(a), NewaddrAddresstype::P2TR) => a.p2tr,
+            (Response::NewAddr(a), _) => a.bech32,
             _ => {
-                return Err(anyhow::anyhow!("Invalid response"));
+                bail!("Invalid response");
             }
         };
 
-        Ok(address)
+        match address {
+            Some(address) => Ok(Some(address)),
+            None => {
+                bail!("No {:?} refund address generated", addresstype)
+            }
+        }
     }
 
     async fn send_get_order_message(
-        client: &mut ClnRpc,
-        amount: u64,
-        blocks: u32,
+        &mut self,
         pubkey: &PublicKey,
-        refund_address: &str,
-        plugin: &Plugin<Arc<PluginState>>,
-        is_public_channel: bool,
+        refund_address: &Option<String>,
+        max_fee_sat: u64,
+        min_onchain_payment_sat: Option<u64>,
+        id: &str,
     ) -> anyhow::Result<()> {
-        let id = make_id();
-
         let params = CreateOrderJsonRpcRequestParams {
-            lsp_balance_sat: amount.to_string(),
-            client_balance_sat: LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE.to_string(),
-            confirms_within_blocks: blocks,
-            channel_expiry_blocks: LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS,
-            token: LSPS1_CREATE_ORDER_TOKEN.to_string(),
-            announce_channel: is_public_channel,
-            refund_onchain_address: refund_address.to_string(),
+            lsp_balance_sat: self.amount.to_string(),
+            client_balance_sat: self.client_balance.to_string(),
+            confirms_within_blocks: self.blocks as u32,
+            channel_expiry_blocks: self.expiry,
+            token: self.token.clone(),
+            announce_channel: self.is_public_channel,
+            refund_onchain_address: refund_address.clone(),
         };
 
         let request = CreateOrderJsonRpcRequest {
             jsonrpc: "2.0".to_string(),
             method: LSPS1_CREATE_ORDER_METHOD.to_string(),
             params,
-            id: id.clone(),
+            id: id.to_string(),
         };
 
-        let json_request = serde_json::to_string(&request)?;
+        // The lease can't end before this, it's moved up once the channel is open
+        let lease_end_height = block_height(&mut self.client).await? + self.expiry;
 
-        // Encode the JSON request to hexadecimal
-        let hex_json_request = hex::encode(json_request.clone());
+        // Store the order before sending it
+        // So the subscription side can find it when the reply comes in
+        let now = unix_timestamp();
+        let order = Lsps1Order {
+            lsp: *pubkey,
+            uri: self.uri.clone(),
+            request: request.clone(),
+            response: None,
+            error: None,
+            payment_status: Lsps1PaymentStatus::Pending,
+            payment_error: None,
+            max_fee_sat,
+            lsp_misreported: None,
+            dry_run: self.dry_run,
+            payment_method: self.payment_method,
+            feerate: self.feerate.clone(),
+            min_onchain_payment_sat,
+            onchain_txid: None,
+            lease_end_height: Some(lease_end_height),
+            lease_warned: false,
+            refund_status: None,
+            refund_started_at: None,
+            created_at: now,
+            updated_at: now,
+        };
 
-        // Convert the message type 37913 to a 2-byte hexadecimal string
-        let message_type_prefix = MESSAGE_TYPE.to_be_bytes(); // Convert to big-endian bytes
-        let hex_message_type_prefix = hex::encode(message_type_prefix);
+        save_order(&mut self.client, &order).await?;
 
-        // Prepend the message type prefix to the hex-encoded JSON request
-        let full_hex_message = format!("{}{}", hex_message_type_prefix, hex_json_request);
+        let state_ref = self.plugin.state().clone();
+        state_ref.data.lock().await.insert(id.to_string(), order);
 
-        client
-            .call(Request::SendCustomMsg(SendcustommsgRequest {
-                msg: full_hex_message,
-                node_id: *pubkey,
-            }))
-            .await?;
+        send_message(&mut self.client, pubkey, &request).await?;
+
+        Ok(())
+    }
+}
 
-        let state_ref = plugin.state().clone();
+// The LSP sends its limits as strings of sats
+fn check_range(name: &str, value: u64, min: &str, max: &str) -> anyhow::Result<()> {
+    let min: u64 = min.parse()?;
+    let max: u64 = max.parse()?;
 
-        // Now, you can lock the mutex asynchronously
-        let mut data = state_ref.data.lock().await;
+    if value < min {
+        bail!(
+            "The {} of {} sat is below the LSP minimum of {} sat",
+            name,
+            value,
+            min
+        );
+    }
 
-        data.insert(id, json_request);
+    if value > max {
+        bail!(
+            "The {} of {} sat is above the LSP maximum of {} sat",
+            name,
+            value,
+            max
+        );
+    }
 
-        Ok(())
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_range_accepts_the_bounds() {
+        assert!(check_range("amount", 100000, "100000", "1000000").is_ok());
+        assert!(check_range("amount", 1000000, "100000", "1000000").is_ok());
+    }
+
+    #[test]
+    fn check_range_rejects_outside_the_bounds() {
+        let err = check_range("amount", 99999, "100000", "1000000").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The amount of 99999 sat is below the LSP minimum of 100000 sat"
+        );
+
+        let err = check_range("client balance", 1000001, "0", "1000000").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The client balance of 1000001 sat is above the LSP maximum of 1000000 sat"
+        );
+    }
+
+    #[test]
+    fn check_range_rejects_bad_lsp_limits() {
+        assert!(check_range("amount", 100000, "", "1000000").is_err());
+        assert!(check_range("amount", 100000, "100000", "lots").is_err());
     }
 }
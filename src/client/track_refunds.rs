@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::Plugin;
+use cln_rpc::{
+    model::{
+        requests::{ListfundsRequest, ListpaysRequest},
+        responses::{ListfundsOutputsStatus, ListpaysPaysStatus},
+    },
+    ClnRpc, Request, Response,
+};
+
+use crate::{
+    constants::{
+        Lsps1Order, Lsps1PaymentMethod, Lsps1RefundStatus, LSPS1_ORDER_REFUNDED_NOTIFICATION,
+        LSPS1_REFUND_OVERDUE_NOTIFICATION, LSPS1_REFUND_TIMEOUT_OPTION, LSPS1_REFUND_TIMEOUT_SECS,
+    },
+    datastore::save_order,
+    PluginState,
+};
+
+use super::utils::{notify_order, unix_timestamp};
+
+pub struct Lsps1TrackRefunds {
+    pub client: ClnRpc,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1TrackRefunds {
+    // Follow up on failed orders until we see our money come back
+    pub async fn track_refunds(&mut self) -> anyhow::Result<()> {
+        let timeout = match self.plugin.option(LSPS1_REFUND_TIMEOUT_OPTION) {
+            Some(cln_plugin::options::Value::Integer(secs)) if secs > 0 => secs as u64,
+            _ => LSPS1_REFUND_TIMEOUT_SECS,
+        };
+
+        // Collect what needs checking first so we don't hold the lock while we ask CLN
+        let orders: Vec<(String, Lsps1Order)> = {
+            let data = self.plugin.state().data.lock().await;
+
+            data.iter()
+                .filter(|(_, order)| order.expects_refund())
+                .map(|(id, order)| (id.clone(), order.clone()))
+                .collect()
+        };
+
+        for (id, order) in orders {
+            let found = match order.payment_method {
+                Lsps1PaymentMethod::Lightning => self.lightning_refund(&order).await?,
+                Lsps1PaymentMethod::Onchain => self
+                    .onchain_refunded(&order)
+                    .await?
+                    .then_some(Lsps1RefundStatus::Refunded),
+            };
+
+            let mut data = self.plugin.state().data.lock().await;
+
+            let order = match data.get_mut(&id) {
+                Some(order) => order,
+                None => continue,
+            };
+
+            let now = unix_timestamp();
+            let started_at = *order.refund_started_at.get_or_insert(now);
+
+            let status = match found {
+                Some(status) => status,
+                None if now.saturating_sub(started_at) > timeout => Lsps1RefundStatus::Overdue,
+                None => Lsps1RefundStatus::Pending,
+            };
+
+            if order.refund_status == Some(status) {
+                continue;
+            }
+
+            order.refund_status = Some(status);
+            order.updated_at = now;
+
+            save_order(&mut self.client, order).await?;
+
+            // Only tell anyone the first time the status changes
+            match status {
+                Lsps1RefundStatus::Refunded => {
+                    log::info!("Order {} was refunded", id);
+                    notify_order(&self.plugin, LSPS1_ORDER_REFUNDED_NOTIFICATION, order).await;
+                }
+                Lsps1RefundStatus::Overdue => {
+                    log::warn!(
+                        "Refund for order {} is overdue, still waiting after {} seconds",
+                        id,
+                        now.saturating_sub(started_at)
+                    );
+                    notify_order(&self.plugin, LSPS1_REFUND_OVERDUE_NOTIFICATION, order).await;
+                }
+                // Nothing left to wait for, so warn about it right away
+                Lsps1RefundStatus::Settled => {
+                    log::warn!(
+                        "Order {} failed but the LSP kept our lightning payment, it has to refund it some other way",
+                        id
+                    );
+                    notify_order(&self.plugin, LSPS1_REFUND_OVERDUE_NOTIFICATION, order).await;
+                }
+                Lsps1RefundStatus::Pending => {
+                    log::info!("Waiting for the refund of order {}", id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The LSP refunds a lightning payment by failing the HTLC back to us
+    // A completed pay means it settled instead, that won't come back over lightning
+    async fn lightning_refund(
+        &mut self,
+        order: &Lsps1Order,
+    ) -> anyhow::Result<Option<Lsps1RefundStatus>> {
+        let bolt11 = match &order.response {
+            Some(response) => response.payment.lightning_invoice.clone(),
+            None => return Ok(None),
+        };
+
+        let res = self
+            .client
+            .call(Request::ListPays(ListpaysRequest {
+                bolt11: Some(bolt11),
+                payment_hash: None,
+                status: None,
+            }))
+            .await?;
+
+        match res {
+            Response::ListPays(n) => {
+                if n.pays
+                    .iter()
+                    .any(|pay| pay.status == ListpaysPaysStatus::COMPLETE)
+                {
+                    Ok(Some(Lsps1RefundStatus::Settled))
+                } else if !n.pays.is_empty()
+                    && n.pays
+                        .iter()
+                        .all(|pay| pay.status == ListpaysPaysStatus::FAILED)
+                {
+                    Ok(Some(Lsps1RefundStatus::Refunded))
+                } else {
+                    Ok(None)
+                }
+            }
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+
+    // On-chain refunds go to the refund address, which we can only see if it's in our wallet
+    async fn onchain_refunded(&mut self, order: &Lsps1Order) -> anyhow::Result<bool> {
+        let refund_address = match &order.request.params.refund_onchain_address {
+            Some(address) => address.clone(),
+            None => {
+                log::debug!("Order {} has no refund address to watch", order.request.id);
+                return Ok(false);
+            }
+        };
+
+        let res = self
+            .client
+            .call(Request::ListFunds(ListfundsRequest { spent: Some(true) }))
+            .await?;
+
+        match res {
+            Response::ListFunds(n) => Ok(n.outputs.iter().any(|output| {
+                output.address.as_ref() == Some(&refund_address)
+                    && output.status != ListfundsOutputsStatus::UNCONFIRMED
+            })),
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+}
@@ -1,8 +1,29 @@
-use std::str::FromStr;
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::bail;
-use cln_rpc::primitives::PublicKey;
+use bitcoin::{Address, Network};
+use cln_plugin::Plugin;
+use cln_rpc::{
+    model::requests::{ConnectRequest, GetinfoRequest, SendcustommsgRequest},
+    primitives::PublicKey,
+    ClnRpc, Request, Response,
+};
 use rand::Rng;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::{
+    constants::{
+        Lsps1Order, PendingRequest, PluginMethodState, LSPS1_RESPONSE_TIMEOUT_OPTION,
+        LSPS1_RESPONSE_TIMEOUT_SECS, MESSAGE_TYPE,
+    },
+    PluginState,
+};
 
 #[derive(Debug)]
 pub struct Uri {
@@ -37,7 +58,173 @@ pub fn make_id() -> String {
     let mut rng = rand::thread_rng();
     let bytes: [u8; 32] = rng.gen();
 
-    let hex_string = hex::encode(bytes);
+    hex::encode(bytes)
+}
+
+// Open a new connection to the node's rpc socket
+pub async fn make_client(plugin: &Plugin<Arc<PluginState>>) -> anyhow::Result<ClnRpc> {
+    let conf = plugin.configuration();
+    let socket_path = Path::new(&conf.lightning_dir).join(&conf.rpc_file);
+
+    ClnRpc::new(socket_path).await
+}
+
+// Let other plugins know something happened to an order
+// A failed notification shouldn't fail whatever triggered it
+pub async fn notify_order(plugin: &Plugin<Arc<PluginState>>, topic: &str, order: &Lsps1Order) {
+    let payload = match serde_json::to_value(order) {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::error!("Failed to serialize order for {}: {}", topic, e);
+            return;
+        }
+    };
+
+    if let Err(e) = plugin
+        .send_custom_notification(topic.to_string(), payload)
+        .await
+    {
+        log::error!("Failed to send {} notification: {}", topic, e);
+    }
+}
+
+// Make sure an address parses and belongs to the network we're running on
+pub fn check_address(address: &str, network: &str) -> anyhow::Result<()> {
+    let network = Network::from_str(network)?;
+
+    if Address::from_str(address)?
+        .require_network(network)
+        .is_err()
+    {
+        bail!("Address {} is not a {} address", address, network);
+    }
+
+    Ok(())
+}
+
+pub async fn block_height(client: &mut ClnRpc) -> anyhow::Result<u32> {
+    let res = client.call(Request::Getinfo(GetinfoRequest {})).await?;
+
+    match res {
+        Response::Getinfo(info) => Ok(info.blockheight),
+        _ => {
+            bail!("Invalid response")
+        }
+    }
+}
+
+// Ignore errors, we might already be connected and the send will fail anyway if we're not
+pub async fn connect(client: &mut ClnRpc, uri: &Uri) {
+    let _ = client
+        .call(Request::Connect(ConnectRequest {
+            id: uri.pubkey.to_string(),
+            host: Some(uri.host.clone()),
+            port: Some(uri.port),
+        }))
+        .await;
+}
+
+// Frame a json-rpc message as an lsps0 custom message and send it to the peer
+pub async fn send_message<T: Serialize>(
+    client: &mut ClnRpc,
+    pubkey: &PublicKey,
+    message: &T,
+) -> anyhow::Result<()> {
+    let json_message = serde_json::to_string(message)?;
 
-    hex_string
+    // Encode the JSON message to hexadecimal
+    let hex_json_message = hex::encode(json_message);
+
+    // Convert the message type 37913 to a 2-byte hexadecimal string
+    let message_type_prefix = MESSAGE_TYPE.to_be_bytes(); // Convert to big-endian bytes
+    let hex_message_type_prefix = hex::encode(message_type_prefix);
+
+    // Prepend the message type prefix to the hex-encoded JSON message
+    let full_hex_message = format!("{}{}", hex_message_type_prefix, hex_json_message);
+
+    client
+        .call(Request::SendCustomMsg(SendcustommsgRequest {
+            msg: full_hex_message,
+            node_id: *pubkey,
+        }))
+        .await?;
+
+    Ok(())
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// Register a waiter for the response to the request sent to this peer with the given id
+// This has to happen before the message is sent so we don't miss a fast reply
+pub async fn register_request(
+    plugin: &Plugin<Arc<PluginState>>,
+    pubkey: &PublicKey,
+    id: &str,
+    method: PluginMethodState,
+) -> oneshot::Receiver<anyhow::Result<serde_json::Value>> {
+    let (responder, receiver) = oneshot::channel();
+
+    let mut pending = plugin.state().pending.lock().await;
+    pending.insert(
+        (*pubkey, id.to_string()),
+        PendingRequest { method, responder },
+    );
+
+    receiver
+}
+
+// Wait for the subscription side to hand us the response to the request
+pub async fn await_response(
+    plugin: &Plugin<Arc<PluginState>>,
+    pubkey: &PublicKey,
+    id: &str,
+    mut receiver: oneshot::Receiver<anyhow::Result<serde_json::Value>>,
+) -> anyhow::Result<serde_json::Value> {
+    let timeout = match plugin.option(LSPS1_RESPONSE_TIMEOUT_OPTION) {
+        Some(cln_plugin::options::Value::Integer(secs)) if secs > 0 => secs as u64,
+        _ => LSPS1_RESPONSE_TIMEOUT_SECS,
+    };
+
+    match tokio::time::timeout(Duration::from_secs(timeout), &mut receiver).await {
+        Ok(response) => received(response),
+        Err(_) => {
+            // Nobody is waiting anymore, drop the entry
+            let pending = plugin
+                .state()
+                .pending
+                .lock()
+                .await
+                .remove(&(*pubkey, id.to_string()));
+
+            // The reply already came in and is still being handled, an order might be
+            // getting paid right now, so wait for how that went instead of timing out
+            if pending.is_none() {
+                log::info!(
+                    "Response with id {} from {} arrived, waiting for it to be handled",
+                    id,
+                    pubkey
+                );
+
+                return received(receiver.await);
+            }
+
+            bail!("Timed out after {} seconds waiting for a response", timeout)
+        }
+    }
+}
+
+fn received(
+    response: Result<anyhow::Result<serde_json::Value>, oneshot::error::RecvError>,
+) -> anyhow::Result<serde_json::Value> {
+    match response {
+        Ok(response) => response,
+        Err(_) => {
+            bail!("Response channel closed before a response was received")
+        }
+    }
 }
@@ -1,23 +1,93 @@
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use cln_rpc::{
-    model::requests::{DecodepayRequest, PayRequest},
+    model::{
+        requests::{DecodepayRequest, PayRequest, WithdrawRequest},
+        responses::DecodepayResponse,
+    },
+    primitives::{Amount, AmountOrAll, Feerate},
     ClnRpc, Request, Response,
 };
 
 use crate::constants::{
-    CreateOrderJsonRpcRequest, CreateOrderJsonRpcResponse, OrderState, PaymentState,
-    LSPS1_MAX_FEE_PAID,
+    CreateOrderJsonRpcRequest, CreateOrderJsonRpcResponse, Lsps1PaymentMethod, Lsps1Quote,
+    OrderState, PaymentState,
 };
 
+use super::utils::check_address;
+
 pub struct Lsps1ValidateAndPay {
-    pub order: String,
+    pub order: CreateOrderJsonRpcRequest,
+    pub max_fee_sat: u64,
+    pub payment_method: Lsps1PaymentMethod,
+    pub feerate: Option<String>,
+    pub min_onchain_payment_sat: Option<u64>,
+    pub network: String,
     pub client: ClnRpc,
     pub order_response_payload: CreateOrderJsonRpcResponse,
 }
 
 impl Lsps1ValidateAndPay {
-    pub async fn validate_and_pay(&mut self) -> anyhow::Result<()> {
-        let order_request = serde_json::from_str::<CreateOrderJsonRpcRequest>(&self.order)?;
+    // Returns the txid when the order was paid on-chain
+    pub async fn validate_and_pay(&mut self) -> anyhow::Result<Option<String>> {
+        self.validate().await?;
+
+        match self.payment_method {
+            Lsps1PaymentMethod::Lightning => {
+                self.pay().await?;
+
+                Ok(None)
+            }
+            Lsps1PaymentMethod::Onchain => Ok(Some(self.pay_onchain().await?)),
+        }
+    }
+
+    pub async fn validate(&mut self) -> anyhow::Result<()> {
+        self.check_order()?;
+
+        match self.payment_method {
+            Lsps1PaymentMethod::Lightning => {
+                let decoded = self.decode_invoice().await?;
+                self.check_invoice(&decoded)
+            }
+            Lsps1PaymentMethod::Onchain => self.check_onchain().map(|_| ()),
+        }
+    }
+
+    // Run every check without paying and report what we found
+    pub async fn quote(&mut self) -> Lsps1Quote {
+        let decoded = self.decode_invoice().await;
+
+        let verdict = match (&self.payment_method, &decoded) {
+            (Lsps1PaymentMethod::Onchain, _) => {
+                self.check_order().and_then(|_| self.check_onchain())
+            }
+            (Lsps1PaymentMethod::Lightning, Ok(decoded)) => {
+                self.check_order().and_then(|_| self.check_invoice(decoded))
+            }
+            (Lsps1PaymentMethod::Lightning, Err(e)) => Err(anyhow!("{}", e)),
+        };
+
+        let result = &self.order_response_payload.result;
+        let decoded = decoded.ok();
+
+        Lsps1Quote {
+            order_id: result.order_id.clone(),
+            fee_total_sat: result.payment.fee_total_sat.clone(),
+            order_total_sat: result.payment.order_total_sat.clone(),
+            invoice_amount_msat: decoded
+                .as_ref()
+                .and_then(|d| d.amount_msat)
+                .map(|a| a.msat()),
+            invoice_expires_at: decoded.as_ref().map(|d| d.created_at + d.expiry),
+            expires_at: result.expires_at.clone(),
+            max_fee_sat: self.max_fee_sat,
+            valid: verdict.is_ok(),
+            error: verdict.err().map(|e| e.to_string()),
+        }
+    }
+
+    fn check_order(&self) -> anyhow::Result<()> {
+        let order_request = &self.order;
         let payload = &self.order_response_payload;
 
         if order_request.id != payload.id {
@@ -36,6 +106,19 @@ impl Lsps1ValidateAndPay {
             bail!("LSP balance mismatch");
         }
 
+        if order_request.params.client_balance_sat != payload.result.client_balance_sat {
+            bail!("Client balance mismatch");
+        }
+
+        // Make sure the LSP took our discount code
+        if order_request.params.token != payload.result.token {
+            bail!(
+                "Token mismatch, sent {:?} but the LSP returned {:?}",
+                order_request.params.token,
+                payload.result.token
+            );
+        }
+
         if payload.result.order_state != OrderState::Created {
             bail!("Order state is not created");
         }
@@ -45,36 +128,62 @@ impl Lsps1ValidateAndPay {
         }
 
         // Make sure you're not paying crazy fees
-        // convert fee_total_sat to u32
-        let fee_total_sat: u32 = payload.result.payment.fee_total_sat.parse()?;
+        let fee_total_sat: u64 = payload.result.payment.fee_total_sat.parse()?;
 
-        if fee_total_sat > LSPS1_MAX_FEE_PAID {
-            bail!("Fee is too high");
+        if fee_total_sat > self.max_fee_sat {
+            bail!(
+                "Fee is too high, quoted {} sat but the limit is {} sat",
+                fee_total_sat,
+                self.max_fee_sat
+            );
         }
 
         let order_total_sat: u64 = payload.result.payment.order_total_sat.parse()?;
+        let client_balance_sat: u64 = payload.result.client_balance_sat.parse()?;
 
-        // We don't support push amounts
-        // So order total and fee total are equal
-        if order_total_sat != fee_total_sat as u64 {
-            bail!("Order total and fee total mismatch");
+        // We pay the fee plus whatever the LSP pushes to our side
+        if order_total_sat != fee_total_sat + client_balance_sat {
+            bail!(
+                "Order total of {} sat is not the fee of {} sat plus the client balance of {} sat",
+                order_total_sat,
+                fee_total_sat,
+                client_balance_sat
+            );
         }
 
+        Ok(())
+    }
+
+    async fn decode_invoice(&mut self) -> anyhow::Result<DecodepayResponse> {
         // Decode payment request
         let res = self
             .client
             .call(Request::DecodePay(DecodepayRequest {
-                bolt11: payload.result.payment.lightning_invoice.clone(),
+                bolt11: self
+                    .order_response_payload
+                    .result
+                    .payment
+                    .lightning_invoice
+                    .clone(),
                 description: None,
             }))
             .await?;
 
-        let decoded = match res {
-            Response::DecodePay(n) => n,
+        match res {
+            Response::DecodePay(n) => Ok(n),
             _ => {
                 bail!("Invalid response");
             }
-        };
+        }
+    }
+
+    fn check_invoice(&self, decoded: &DecodepayResponse) -> anyhow::Result<()> {
+        let order_total_sat: u64 = self
+            .order_response_payload
+            .result
+            .payment
+            .order_total_sat
+            .parse()?;
 
         if let Some(invoice_amount) = decoded.amount_msat {
             if invoice_amount.msat() != order_total_sat * 1000 {
@@ -84,11 +193,93 @@ impl Lsps1ValidateAndPay {
             bail!("No invoice amount");
         }
 
+        Ok(())
+    }
+
+    // The LSP has to give us an address on our network to pay to
+    fn check_onchain(&self) -> anyhow::Result<()> {
+        let payment = &self.order_response_payload.result.payment;
+
+        let address = match &payment.onchain_address {
+            Some(address) => address,
+            None => {
+                bail!("LSP did not provide an on-chain address")
+            }
+        };
+
+        check_address(address, &self.network)?;
+
+        let order_total_sat: u64 = payment.order_total_sat.parse()?;
+
+        if order_total_sat == 0 {
+            bail!("Order total is zero");
+        }
+
+        // The LSP's minimum is for what we send, the fee plus any client balance
+        if let Some(min) = self.min_onchain_payment_sat {
+            if order_total_sat < min {
+                bail!(
+                    "Order total of {} sat is below the LSP minimum of {} sat for on-chain payments",
+                    order_total_sat,
+                    min
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn pay_onchain(&mut self) -> anyhow::Result<String> {
+        let payment = &self.order_response_payload.result.payment;
+
+        let address = match &payment.onchain_address {
+            Some(address) => address.clone(),
+            None => {
+                bail!("LSP did not provide an on-chain address")
+            }
+        };
+
+        let order_total_sat: u64 = payment.order_total_sat.parse()?;
+
+        let feerate = match &self.feerate {
+            Some(feerate) => Some(Feerate::try_from(feerate.as_str())?),
+            None => None,
+        };
+
+        let res = self
+            .client
+            .call(Request::Withdraw(WithdrawRequest {
+                destination: address,
+                satoshi: Some(AmountOrAll::Amount(Amount::from_sat(order_total_sat))),
+                feerate,
+                minconf: None,
+                utxos: None,
+            }))
+            .await?;
+
+        match res {
+            Response::Withdraw(n) => {
+                log::info!("On-chain payment sent in {}", n.txid);
+
+                Ok(n.txid)
+            }
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+
+    async fn pay(&mut self) -> anyhow::Result<()> {
         // Pay in the invoice
         let res = self
             .client
             .call(Request::Pay(PayRequest {
-                bolt11: payload.result.payment.lightning_invoice.clone(),
+                bolt11: self
+                    .order_response_payload
+                    .result
+                    .payment
+                    .lightning_invoice
+                    .clone(),
                 amount_msat: None,
                 maxfeepercent: None,
                 description: None,
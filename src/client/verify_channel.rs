@@ -0,0 +1,218 @@
+use anyhow::bail;
+use cln_rpc::{
+    model::requests::ListpeerchannelsRequest, primitives::PublicKey, ClnRpc, Request, Response,
+};
+
+use crate::constants::{CreateOrderJsonRpcRequestParams, CreateOrderJsonRpcResponseResult};
+
+pub struct Lsps1VerifyChannel {
+    pub client: ClnRpc,
+    pub lsp: PublicKey,
+    pub request: CreateOrderJsonRpcRequestParams,
+    pub order: CreateOrderJsonRpcResponseResult,
+}
+
+impl Lsps1VerifyChannel {
+    // The LSP's reply has to stick to what we ordered, it can't shrink the channel later
+    // Returns what doesn't match, if anything
+    pub fn check_reply(
+        request: &CreateOrderJsonRpcRequestParams,
+        order: &CreateOrderJsonRpcResponseResult,
+    ) -> Option<String> {
+        let mut mismatches = Vec::new();
+
+        if order.lsp_balance_sat != request.lsp_balance_sat {
+            mismatches.push(format!(
+                "LSP balance is {} sat, we ordered {} sat",
+                order.lsp_balance_sat, request.lsp_balance_sat
+            ));
+        }
+
+        if order.client_balance_sat != request.client_balance_sat {
+            mismatches.push(format!(
+                "client balance is {} sat, we ordered {} sat",
+                order.client_balance_sat, request.client_balance_sat
+            ));
+        }
+
+        if order.announce_channel != request.announce_channel {
+            mismatches.push(format!(
+                "announce_channel is {}, we ordered {}",
+                order.announce_channel, request.announce_channel
+            ));
+        }
+
+        if order.channel_expiry_blocks != request.channel_expiry_blocks {
+            mismatches.push(format!(
+                "channel expiry is {} blocks, we ordered {} blocks",
+                order.channel_expiry_blocks, request.channel_expiry_blocks
+            ));
+        }
+
+        if mismatches.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "Order doesn't match the request: {}",
+                mismatches.join(", ")
+            ))
+        }
+    }
+
+    // Check the channel the LSP says it opened against what our node sees and what we ordered
+    // Returns what the LSP got wrong, if anything
+    pub async fn verify_channel(&mut self) -> anyhow::Result<Option<String>> {
+        let channel = match &self.order.channel {
+            Some(channel) => channel,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        let outpoint = channel
+            .funding_outpoint
+            .split_once(':')
+            .and_then(|(txid, outnum)| Some((txid, outnum.parse::<u32>().ok()?)));
+
+        let (txid, outnum) = match outpoint {
+            Some(outpoint) => outpoint,
+            None => {
+                return Ok(Some(format!(
+                    "Invalid funding outpoint {}",
+                    channel.funding_outpoint
+                )));
+            }
+        };
+
+        let res = self
+            .client
+            .call(Request::ListPeerChannels(ListpeerchannelsRequest {
+                id: Some(self.lsp),
+            }))
+            .await?;
+
+        let channels = match res {
+            Response::ListPeerChannels(r) => r.channels.unwrap_or_default(),
+            _ => {
+                bail!("Invalid response");
+            }
+        };
+
+        let peer_channel = channels
+            .into_iter()
+            .find(|c| c.funding_txid.as_deref() == Some(txid) && c.funding_outnum == Some(outnum));
+
+        let peer_channel = match peer_channel {
+            Some(peer_channel) => peer_channel,
+            None => {
+                return Ok(Some(format!(
+                    "No channel with funding outpoint {} found with {}",
+                    channel.funding_outpoint, self.lsp
+                )));
+            }
+        };
+
+        let lsp_balance_sat: u64 = self.request.lsp_balance_sat.parse()?;
+        let client_balance_sat: u64 = self.request.client_balance_sat.parse()?;
+
+        if let Some(total) = peer_channel.total_msat {
+            if total.msat() != (lsp_balance_sat + client_balance_sat) * 1000 {
+                return Ok(Some(format!(
+                    "Channel capacity is {} sat, expected {} sat",
+                    total.msat() / 1000,
+                    lsp_balance_sat + client_balance_sat
+                )));
+            }
+        }
+
+        // Whatever the LSP pushed to us at open is not part of its balance
+        if let Some(funding) = peer_channel.funding {
+            let remote_funds = funding.remote_funds_msat.map_or(0, |a| a.msat());
+            let pushed = funding.pushed_msat.map_or(0, |a| a.msat());
+
+            if remote_funds.saturating_sub(pushed) != lsp_balance_sat * 1000 {
+                return Ok(Some(format!(
+                    "LSP balance at open is {} sat, expected {} sat",
+                    remote_funds.saturating_sub(pushed) / 1000,
+                    lsp_balance_sat
+                )));
+            }
+        }
+
+        if let Some(private) = peer_channel.private {
+            if private == self.request.announce_channel {
+                return Ok(Some(format!(
+                    "Channel is {}, expected {}",
+                    if private { "private" } else { "public" },
+                    if self.request.announce_channel {
+                        "public"
+                    } else {
+                        "private"
+                    }
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> CreateOrderJsonRpcRequestParams {
+        CreateOrderJsonRpcRequestParams {
+            lsp_balance_sat: "1000000".to_string(),
+            client_balance_sat: "0".to_string(),
+            confirms_within_blocks: 6,
+            channel_expiry_blocks: 13000,
+            token: String::new(),
+            refund_onchain_address: None,
+            announce_channel: true,
+        }
+    }
+
+    fn reply(lsp_balance_sat: &str, announce_channel: bool) -> CreateOrderJsonRpcResponseResult {
+        serde_json::from_value(serde_json::json!({
+            "order_id": "order",
+            "lsp_balance_sat": lsp_balance_sat,
+            "client_balance_sat": "0",
+            "confirms_within_blocks": 6,
+            "channel_expiry_blocks": 13000,
+            "token": "",
+            "created_at": "2024-01-01T00:00:00.000Z",
+            "expires_at": "2024-01-01T01:00:00.000Z",
+            "announce_channel": announce_channel,
+            "order_state": "COMPLETED",
+            "payment": {
+                "state": "PAID",
+                "fee_total_sat": "1000",
+                "order_total_sat": "1000",
+                "lightning_invoice": "lnbc",
+                "onchain_address": null,
+                "min_onchain_payment_confirmations": null,
+                "min_fee_for_0conf": null,
+                "onchain_payment": null,
+            },
+            "channel": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn check_reply_accepts_what_we_ordered() {
+        assert_eq!(
+            Lsps1VerifyChannel::check_reply(&request(), &reply("1000000", true)),
+            None
+        );
+    }
+
+    #[test]
+    fn check_reply_flags_a_smaller_private_channel() {
+        let reason = Lsps1VerifyChannel::check_reply(&request(), &reply("500000", false)).unwrap();
+
+        assert!(reason.contains("LSP balance is 500000 sat, we ordered 1000000 sat"));
+        assert!(reason.contains("announce_channel is false, we ordered true"));
+    }
+}
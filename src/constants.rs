@@ -1,32 +1,481 @@
+use cln_rpc::primitives::PublicKey;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
 
 pub const MESSAGE_TYPE: u16 = 37913u16;
 
+pub const LSPS0_LIST_PROTOCOLS_METHOD: &str = "lsps0.list_protocols";
+pub const LSPS1_PROTOCOL: u32 = 1;
+
 pub const LSPS1_GET_INFO_METHOD: &str = "lsps1.get_info";
 pub const LSPS1_CREATE_ORDER_METHOD: &str = "lsps1.create_order";
 pub const LSPS1_GET_ORDER_METHOD: &str = "lsps1.get_order";
-pub const LSPS1_MAX_FEE_PAID: u32 = 100000;
+pub const LSPS1_MAX_FEE_PAID: u64 = 100000;
+
+pub const LSPS1_MAX_FEE_SAT_OPTION: &str = "lsps1-max-fee-sat";
+pub const LSPS1_MAX_FEE_PPM_OPTION: &str = "lsps1-max-fee-ppm";
+pub const LSPS1_LSP_MAX_FEE_SAT_OPTION: &str = "lsps1-lsp-max-fee-sat";
+
+pub const LSPS1_ORDER_CREATED_NOTIFICATION: &str = "lsps1_order_created";
+pub const LSPS1_ORDER_PAID_NOTIFICATION: &str = "lsps1_order_paid";
+pub const LSPS1_ORDER_COMPLETED_NOTIFICATION: &str = "lsps1_order_completed";
+pub const LSPS1_ORDER_FAILED_NOTIFICATION: &str = "lsps1_order_failed";
+
+pub const DATASTORE_PLUGIN_KEY: &str = "cln-lightning-liquidity";
+pub const DATASTORE_ORDERS_KEY: &str = "orders";
+
+pub const LSPS1_CONFIRM_PAYMENT_OPTION: &str = "lsps1-confirm-payment";
+pub const LSPS1_AUTO_PAY_MAX_SAT_OPTION: &str = "lsps1-auto-pay-max-sat";
+
+pub const LSPS1_POLL_INTERVAL_OPTION: &str = "lsps1-poll-interval";
+pub const LSPS1_POLL_INTERVAL_SECS: u64 = 60;
+
+pub const LSPS1_GET_INFO_CACHE_SECS: u64 = 600;
+
+pub const LSPS1_RESPONSE_TIMEOUT_OPTION: &str = "lsps1-response-timeout";
+pub const LSPS1_RESPONSE_TIMEOUT_SECS: u64 = 60;
+
+pub const LSPS1_CHANNEL_EXPIRY_BLOCKS_OPTION: &str = "lsps1-channel-expiry-blocks";
+pub const LSPS1_LEASE_WARNING_BLOCKS_OPTION: &str = "lsps1-lease-warning-blocks";
+pub const LSPS1_LEASE_WARNING_BLOCKS: u32 = 1008;
+pub const LSPS1_LEASE_EXPIRING_NOTIFICATION: &str = "lsps1_lease_expiring";
+
+pub const LSPS1_REFUND_TIMEOUT_OPTION: &str = "lsps1-refund-timeout";
+pub const LSPS1_REFUND_TIMEOUT_SECS: u64 = 86400;
+pub const LSPS1_ORDER_REFUNDED_NOTIFICATION: &str = "lsps1_order_refunded";
+pub const LSPS1_REFUND_OVERDUE_NOTIFICATION: &str = "lsps1_refund_overdue";
+
+pub const LSPS1_SERVER_OPTION: &str = "lsps1-server";
+pub const LSPS1_SERVER_MIN_LSP_BALANCE_SAT_OPTION: &str = "lsps1-server-min-lsp-balance-sat";
+pub const LSPS1_SERVER_MIN_LSP_BALANCE_SAT: u64 = 100000;
+pub const LSPS1_SERVER_MAX_LSP_BALANCE_SAT_OPTION: &str = "lsps1-server-max-lsp-balance-sat";
+pub const LSPS1_SERVER_MAX_LSP_BALANCE_SAT: u64 = 10000000;
+pub const LSPS1_SERVER_MAX_CLIENT_BALANCE_SAT_OPTION: &str = "lsps1-server-max-client-balance-sat";
+pub const LSPS1_SERVER_MAX_CHANNEL_EXPIRY_BLOCKS_OPTION: &str =
+    "lsps1-server-max-channel-expiry-blocks";
+pub const LSPS1_SERVER_BASE_FEE_SAT_OPTION: &str = "lsps1-server-base-fee-sat";
+pub const LSPS1_SERVER_BASE_FEE_SAT: u64 = 1000;
+pub const LSPS1_SERVER_FEE_PPM_OPTION: &str = "lsps1-server-fee-ppm";
+pub const LSPS1_SERVER_LEASE_FEE_PPB_OPTION: &str = "lsps1-server-lease-fee-ppb";
+pub const LSPS1_SERVER_FAST_CONFIRM_BLOCKS_OPTION: &str = "lsps1-server-fast-confirm-blocks";
+pub const LSPS1_SERVER_FAST_CONFIRM_BLOCKS: u64 = 6;
+pub const LSPS1_SERVER_FAST_CONFIRM_FEE_SAT_OPTION: &str = "lsps1-server-fast-confirm-fee-sat";
+pub const LSPS1_SERVER_WEBSITE_OPTION: &str = "lsps1-server-website";
+pub const LSPS1_SERVER_MIN_CHANNEL_CONFIRMATIONS: u32 = 3;
+pub const LSPS1_SERVER_ORDER_EXPIRY_SECS: u64 = 3600;
+pub const LSPS1_SERVER_INVOICE_LABEL_PREFIX: &str = "lsps1-";
+pub const LSPS1_SERVER_HOLD_TIMEOUT_SECS: u64 = 600;
+pub const LSPS1_SERVER_ALLOW_OPTION: &str = "lsps1-server-allow";
+pub const LSPS1_SERVER_DENY_OPTION: &str = "lsps1-server-deny";
+pub const LSPS1_SERVER_MAX_ORDERS_PER_HOUR_OPTION: &str = "lsps1-server-max-orders-per-hour";
+pub const LSPS1_SERVER_MAX_ORDERS_PER_HOUR: u64 = 10;
+pub const LSPS1_SERVER_MAX_OPEN_ORDERS_OPTION: &str = "lsps1-server-max-open-orders";
+pub const LSPS1_SERVER_MAX_OPEN_ORDERS: u64 = 3;
+pub const LSPS1_SERVER_REQUIRE_PUBLIC_CHANNEL_OPTION: &str = "lsps1-server-require-public-channel";
+pub const LSPS1_SERVER_MIN_CONNECTED_SECS_OPTION: &str = "lsps1-server-min-connected-secs";
+// Roughly how long a block takes, to turn lease blocks into a date
+pub const BLOCK_TIME_SECS: u64 = 600;
 
+pub const DATASTORE_SERVER_ORDERS_KEY: &str = "server-orders";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PluginMethodState {
-    None,
+    ListProtocols,
     GetInfo,
     SendOrder,
     GetOrder,
     // Add other states as needed
 }
 
-impl ToString for PluginMethodState {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for PluginMethodState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PluginMethodState::None => "None".to_string(),
-            PluginMethodState::GetInfo => "GetInfo".to_string(),
-            PluginMethodState::SendOrder => "SendOrder".to_string(),
-            PluginMethodState::GetOrder => "GetOrder".to_string(),
+            PluginMethodState::ListProtocols => write!(f, "ListProtocols"),
+            PluginMethodState::GetInfo => write!(f, "GetInfo"),
+            PluginMethodState::SendOrder => write!(f, "SendOrder"),
+            PluginMethodState::GetOrder => write!(f, "GetOrder"),
             // Handle other states
         }
     }
 }
 
+// An outgoing request waiting for its response
+// Keyed by the peer it was sent to and its json rpc id
+pub struct PendingRequest {
+    pub method: PluginMethodState,
+    pub responder: oneshot::Sender<anyhow::Result<serde_json::Value>>,
+}
+
+pub type PendingRequestKey = (PublicKey, String);
+
+// The parts of a payment for one of our orders that we're holding on to
+// Everyone waiting gets told whether the channel opened
+pub struct HeldPayment {
+    pub amount_msat: u64,
+    pub opening: bool,
+    pub result: watch::Sender<Option<bool>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lsps1PaymentStatus {
+    Pending,
+    Paid,
+    Failed,
+    DryRun,
+    AwaitingConfirmation,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Lsps1RefundStatus {
+    Pending,
+    Refunded,
+    Overdue,
+    // The LSP took our lightning payment, it can only pay us back some other way
+    Settled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Lsps1PaymentMethod {
+    #[default]
+    Lightning,
+    Onchain,
+}
+
+// What a dry run found out about an order without paying for it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Lsps1Quote {
+    pub order_id: String,
+    pub fee_total_sat: String,
+    pub order_total_sat: String,
+    pub invoice_amount_msat: Option<u64>,
+    pub invoice_expires_at: Option<u64>,
+    pub expires_at: String,
+    pub max_fee_sat: u64,
+    pub valid: bool,
+    pub error: Option<String>,
+}
+
+// An order we sent along with the LSP it was sent to
+// Replies for it are only accepted from that LSP
+// This is what gets persisted in the datastore
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Lsps1Order {
+    pub lsp: PublicKey,
+    #[serde(default)]
+    pub uri: String,
+    pub request: CreateOrderJsonRpcRequest,
+    pub response: Option<CreateOrderJsonRpcResponseResult>,
+    pub error: Option<JsonRpcError>,
+    pub payment_status: Lsps1PaymentStatus,
+    pub payment_error: Option<String>,
+    #[serde(default = "default_max_fee_sat")]
+    pub max_fee_sat: u64,
+    #[serde(default)]
+    pub lsp_misreported: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub payment_method: Lsps1PaymentMethod,
+    #[serde(default)]
+    pub feerate: Option<String>,
+    // Smallest on-chain payment the LSP takes, from its get_info
+    #[serde(default)]
+    pub min_onchain_payment_sat: Option<u64>,
+    #[serde(default)]
+    pub onchain_txid: Option<String>,
+    // Block height the LSP may close the channel after
+    #[serde(default)]
+    pub lease_end_height: Option<u32>,
+    #[serde(default)]
+    pub lease_warned: bool,
+    #[serde(default)]
+    pub refund_status: Option<Lsps1RefundStatus>,
+    #[serde(default)]
+    pub refund_started_at: Option<u64>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// An order a client placed with us when we act as the LSP
+// This is what gets persisted in the datastore
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Lsps1ServerOrder {
+    pub client: PublicKey,
+    pub order: CreateOrderJsonRpcResponseResult,
+    pub label: String,
+    #[serde(default)]
+    pub payment_hash: Option<String>,
+    // Hex of the invoice's payment secret, payments have to carry it in the onion
+    #[serde(default)]
+    pub payment_secret: Option<String>,
+    #[serde(default)]
+    pub fee_breakdown: Option<Lsps1ServerFeeBreakdown>,
+    pub error: Option<String>,
+    pub expires_at: u64,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Lsps1ServerOrder {
+    // What we need to fund the channel, our side plus what we push to the client
+    pub fn channel_sat(&self) -> u64 {
+        self.order.lsp_balance_sat.parse::<u64>().unwrap_or(0)
+            + self.order.client_balance_sat.parse::<u64>().unwrap_or(0)
+    }
+
+    // Still waiting for a payment that can't come anymore
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.order.order_state == OrderState::Created
+            && self.order.payment.state == PaymentState::ExpectPayment
+            && now > self.expires_at
+    }
+}
+
+// Where our on-chain funds are going as the LSP
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Lsps1ServerCapital {
+    pub wallet_sat: u64,
+    pub committed_sat: u64,
+    pub in_flight_sat: u64,
+    pub available_sat: u64,
+}
+
+// How we got to the fee we charged for an order
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Lsps1ServerFeeBreakdown {
+    pub base_fee_sat: u64,
+    pub ppm_fee_sat: u64,
+    pub lease_fee_sat: u64,
+    pub onchain_fee_sat: u64,
+    pub fast_confirm_fee_sat: u64,
+    pub feerate_perkw: Option<u32>,
+    pub fee_total_sat: u64,
+}
+
+// Any json rpc request a peer sends us
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub id: String,
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+// Just enough of a json rpc response to find the request it belongs to
+#[derive(Debug, Deserialize)]
+pub struct JsonRpcResponseId {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRpcErrorResponse {
+    pub id: String,
+    pub jsonrpc: String,
+    pub error: JsonRpcError,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<serde_json::Value>,
+}
+
+fn default_max_fee_sat() -> u64 {
+    LSPS1_MAX_FEE_PAID
+}
+
+impl Lsps1Order {
+    // Orders that can't change anymore don't need to be followed up on
+    pub fn is_terminal(&self) -> bool {
+        if self.dry_run {
+            return true;
+        }
+
+        match &self.response {
+            Some(response) => {
+                response.order_state != OrderState::Created
+                    || response.payment.state == PaymentState::Refunded
+            }
+            None => true,
+        }
+    }
+
+    // We paid but the order failed, so the LSP owes us our money back
+    // A lightning payment the LSP failed back shows up as a failed pay, so those count too
+    pub fn expects_refund(&self) -> bool {
+        if matches!(
+            self.refund_status,
+            Some(Lsps1RefundStatus::Refunded | Lsps1RefundStatus::Settled)
+        ) {
+            return false;
+        }
+
+        let attempted = match self.payment_method {
+            Lsps1PaymentMethod::Lightning => matches!(
+                self.payment_status,
+                Lsps1PaymentStatus::Paid | Lsps1PaymentStatus::Failed
+            ),
+            Lsps1PaymentMethod::Onchain => self.payment_status == Lsps1PaymentStatus::Paid,
+        };
+
+        if !attempted {
+            return false;
+        }
+
+        self.response.as_ref().is_some_and(|response| {
+            response.order_state == OrderState::Failed
+                && matches!(
+                    response.payment.state,
+                    PaymentState::Refunded | PaymentState::Hold
+                )
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lsps0ErrorCode {
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    OptionMismatch,
+    ClientRejected,
+    NotFound,
+    Unknown(i64),
+}
+
+impl From<i64> for Lsps0ErrorCode {
+    fn from(code: i64) -> Self {
+        match code {
+            -32700 => Lsps0ErrorCode::ParseError,
+            -32600 => Lsps0ErrorCode::InvalidRequest,
+            -32601 => Lsps0ErrorCode::MethodNotFound,
+            -32602 => Lsps0ErrorCode::InvalidParams,
+            -32603 => Lsps0ErrorCode::InternalError,
+            100 => Lsps0ErrorCode::OptionMismatch,
+            101 => Lsps0ErrorCode::ClientRejected,
+            102 => Lsps0ErrorCode::NotFound,
+            code => Lsps0ErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<Lsps0ErrorCode> for i64 {
+    fn from(code: Lsps0ErrorCode) -> Self {
+        match code {
+            Lsps0ErrorCode::ParseError => -32700,
+            Lsps0ErrorCode::InvalidRequest => -32600,
+            Lsps0ErrorCode::MethodNotFound => -32601,
+            Lsps0ErrorCode::InvalidParams => -32602,
+            Lsps0ErrorCode::InternalError => -32603,
+            Lsps0ErrorCode::OptionMismatch => 100,
+            Lsps0ErrorCode::ClientRejected => 101,
+            Lsps0ErrorCode::NotFound => 102,
+            Lsps0ErrorCode::Unknown(code) => code,
+        }
+    }
+}
+
+impl std::fmt::Display for Lsps0ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lsps0ErrorCode::ParseError => write!(f, "Parse error"),
+            Lsps0ErrorCode::InvalidRequest => write!(f, "Invalid request"),
+            Lsps0ErrorCode::MethodNotFound => write!(f, "Method not found"),
+            Lsps0ErrorCode::InvalidParams => write!(f, "Invalid params"),
+            Lsps0ErrorCode::InternalError => write!(f, "Internal error"),
+            Lsps0ErrorCode::OptionMismatch => write!(f, "Option mismatch"),
+            Lsps0ErrorCode::ClientRejected => write!(f, "Client rejected"),
+            Lsps0ErrorCode::NotFound => write!(f, "Not found"),
+            Lsps0ErrorCode::Unknown(code) => write!(f, "Unknown error {}", code),
+        }
+    }
+}
+
+impl JsonRpcError {
+    pub fn new(code: Lsps0ErrorCode, message: &str) -> Self {
+        Self {
+            code: code.into(),
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    // The error we send when a client asks for something outside our options
+    pub fn option_mismatch(property: &str) -> Self {
+        Self {
+            code: Lsps0ErrorCode::OptionMismatch.into(),
+            message: format!("Option mismatch for {}", property),
+            data: Some(serde_json::json!({ "property": property })),
+        }
+    }
+
+    pub fn code(&self) -> Lsps0ErrorCode {
+        Lsps0ErrorCode::from(self.code)
+    }
+
+    // The LSP tells us which property it didn't like in data.property
+    pub fn property(&self) -> Option<&str> {
+        self.data
+            .as_ref()
+            .and_then(|data| data.get("property"))
+            .and_then(|property| property.as_str())
+    }
+}
+
+impl std::fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LSP returned error {} ({}): {}",
+            self.code,
+            self.code(),
+            self.message
+        )?;
+
+        if let Some(property) = self.property() {
+            // Point the user to the cli param they need to change
+            let param = match property {
+                "lsp_balance_sat" => "amount",
+                "confirms_within_blocks" => "blocks",
+                "announce_channel" => "type",
+                "order_id" => "orderid",
+                _ => property,
+            };
+
+            write!(f, ", check the `{}` parameter", param)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListProtocolsJsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: serde_json::Value,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListProtocolsJsonRpcResponse {
+    pub id: String,
+    pub jsonrpc: String,
+    pub result: ListProtocolsJsonRpcResponseResult,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListProtocolsJsonRpcResponseResult {
+    pub protocols: Vec<u32>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GetInfoJsonRpcRequest {
     pub jsonrpc: String,
@@ -42,13 +491,13 @@ pub struct GetInfoJsonRpcResponse {
     pub result: GetInfoJsonRpcResponseResult,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GetInfoJsonRpcResponseResult {
     pub options: GetInfoJsonRpcResponseOptions,
     pub website: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GetInfoJsonRpcResponseOptions {
     pub min_channel_confirmations: u32,
     pub min_onchain_payment_confirmations: Option<u32>,
@@ -63,7 +512,7 @@ pub struct GetInfoJsonRpcResponseOptions {
     pub max_channel_balance_sat: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateOrderJsonRpcRequest {
     pub id: String,
     pub jsonrpc: String,
@@ -71,18 +520,20 @@ pub struct CreateOrderJsonRpcRequest {
     pub params: CreateOrderJsonRpcRequestParams,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateOrderJsonRpcRequestParams {
     pub lsp_balance_sat: String,
     pub client_balance_sat: String,
     pub confirms_within_blocks: u32,
     pub channel_expiry_blocks: u32,
+    #[serde(default)]
     pub token: String,
-    pub refund_onchain_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refund_onchain_address: Option<String>,
     pub announce_channel: bool,
 }
 
-pub const LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE: &str = "0";
+pub const LSPS1_CREATE_ORDER_CLIENT_SAT_BALANCE: u64 = 0;
 pub const LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS: u32 = 13000;
 pub const LSPS1_CREATE_ORDER_TOKEN: &str = "";
 
@@ -164,3 +615,128 @@ pub struct GetOrderJsonRpcRequest {
 pub struct GetOrderJsonRpcRequestParams {
     pub order_id: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes_round_trip() {
+        for code in [-32700, -32600, -32601, -32602, -32603, 100, 101, 102, 1000] {
+            assert_eq!(i64::from(Lsps0ErrorCode::from(code)), code);
+        }
+
+        assert_eq!(Lsps0ErrorCode::from(101), Lsps0ErrorCode::ClientRejected);
+        assert_eq!(Lsps0ErrorCode::from(1000), Lsps0ErrorCode::Unknown(1000));
+    }
+
+    #[test]
+    fn error_display_points_to_the_cli_param() {
+        let error: JsonRpcError = serde_json::from_value(serde_json::json!({
+            "code": 100,
+            "message": "Option mismatch",
+            "data": { "property": "lsp_balance_sat" },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "LSP returned error 100 (Option mismatch): Option mismatch, check the `amount` parameter"
+        );
+    }
+
+    #[test]
+    fn error_display_without_property() {
+        let error = JsonRpcError::new(Lsps0ErrorCode::NotFound, "Order not found");
+
+        assert_eq!(
+            error.to_string(),
+            "LSP returned error 102 (Not found): Order not found"
+        );
+    }
+
+    #[test]
+    fn create_order_params_without_token() {
+        let params: CreateOrderJsonRpcRequestParams = serde_json::from_value(serde_json::json!({
+            "lsp_balance_sat": "1000000",
+            "client_balance_sat": "0",
+            "confirms_within_blocks": 6,
+            "channel_expiry_blocks": 13000,
+            "announce_channel": true,
+        }))
+        .unwrap();
+
+        assert_eq!(params.token, "");
+        assert_eq!(params.refund_onchain_address, None);
+    }
+
+    fn failed_order(payment_status: &str, payment_method: &str) -> Lsps1Order {
+        serde_json::from_value(serde_json::json!({
+            "lsp": "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+            "request": {
+                "id": "1",
+                "jsonrpc": "2.0",
+                "method": "lsps1.create_order",
+                "params": {
+                    "lsp_balance_sat": "1000000",
+                    "client_balance_sat": "0",
+                    "confirms_within_blocks": 6,
+                    "channel_expiry_blocks": 13000,
+                    "announce_channel": true,
+                },
+            },
+            "response": {
+                "order_id": "order",
+                "lsp_balance_sat": "1000000",
+                "client_balance_sat": "0",
+                "confirms_within_blocks": 6,
+                "channel_expiry_blocks": 13000,
+                "token": "",
+                "created_at": "2024-01-01T00:00:00.000Z",
+                "expires_at": "2024-01-01T01:00:00.000Z",
+                "announce_channel": true,
+                "order_state": "FAILED",
+                "payment": {
+                    "state": "REFUNDED",
+                    "fee_total_sat": "1000",
+                    "order_total_sat": "1000",
+                    "lightning_invoice": "lnbc",
+                    "onchain_address": null,
+                    "min_onchain_payment_confirmations": null,
+                    "min_fee_for_0conf": null,
+                    "onchain_payment": null,
+                },
+                "channel": null,
+            },
+            "error": null,
+            "payment_status": payment_status,
+            "payment_error": null,
+            "payment_method": payment_method,
+            "created_at": 0,
+            "updated_at": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn expects_refund_after_a_failed_lightning_pay() {
+        assert!(failed_order("paid", "lightning").expects_refund());
+        assert!(failed_order("failed", "lightning").expects_refund());
+        assert!(!failed_order("failed", "onchain").expects_refund());
+        assert!(!failed_order("pending", "lightning").expects_refund());
+
+        let mut order = failed_order("failed", "lightning");
+        order.refund_status = Some(Lsps1RefundStatus::Settled);
+        assert!(!order.expects_refund());
+    }
+
+    #[test]
+    fn error_display_keeps_unknown_properties() {
+        let error = JsonRpcError::option_mismatch("refund_onchain_address");
+
+        assert_eq!(error.property(), Some("refund_onchain_address"));
+        assert!(error
+            .to_string()
+            .ends_with("check the `refund_onchain_address` parameter"));
+    }
+}
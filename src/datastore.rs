@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use cln_rpc::{
+    model::requests::{DatastoreMode, DatastoreRequest, ListdatastoreRequest},
+    ClnRpc, Request, Response,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::constants::{
+    Lsps1Order, Lsps1ServerOrder, DATASTORE_ORDERS_KEY, DATASTORE_PLUGIN_KEY,
+    DATASTORE_SERVER_ORDERS_KEY,
+};
+
+fn key(kind: &str, id: &str) -> Vec<String> {
+    vec![
+        DATASTORE_PLUGIN_KEY.to_string(),
+        kind.to_string(),
+        id.to_string(),
+    ]
+}
+
+// Write the order to the datastore, replacing whatever was there before
+pub async fn save_order(client: &mut ClnRpc, order: &Lsps1Order) -> anyhow::Result<()> {
+    save(client, key(DATASTORE_ORDERS_KEY, &order.request.id), order).await
+}
+
+// Read back every order we stored, keyed by the json rpc id of the create_order request
+pub async fn load_orders(client: &mut ClnRpc) -> anyhow::Result<HashMap<String, Lsps1Order>> {
+    let orders: Vec<Lsps1Order> = load(client, DATASTORE_ORDERS_KEY).await?;
+
+    Ok(orders
+        .into_iter()
+        .map(|order| (order.request.id.clone(), order))
+        .collect())
+}
+
+// Same for orders clients placed with us, keyed by order id
+pub async fn save_server_order(
+    client: &mut ClnRpc,
+    order: &Lsps1ServerOrder,
+) -> anyhow::Result<()> {
+    save(
+        client,
+        key(DATASTORE_SERVER_ORDERS_KEY, &order.order.order_id),
+        order,
+    )
+    .await
+}
+
+pub async fn load_server_orders(
+    client: &mut ClnRpc,
+) -> anyhow::Result<HashMap<String, Lsps1ServerOrder>> {
+    let orders: Vec<Lsps1ServerOrder> = load(client, DATASTORE_SERVER_ORDERS_KEY).await?;
+
+    Ok(orders
+        .into_iter()
+        .map(|order| (order.order.order_id.clone(), order))
+        .collect())
+}
+
+async fn save<T: Serialize>(
+    client: &mut ClnRpc,
+    key: Vec<String>,
+    value: &T,
+) -> anyhow::Result<()> {
+    let res = client
+        .call(Request::Datastore(DatastoreRequest {
+            key,
+            string: Some(serde_json::to_string(value)?),
+            hex: None,
+            mode: Some(DatastoreMode::CREATE_OR_REPLACE),
+            generation: None,
+        }))
+        .await?;
+
+    match res {
+        Response::Datastore(_) => Ok(()),
+        _ => {
+            bail!("Invalid response");
+        }
+    }
+}
+
+async fn load<T: DeserializeOwned>(client: &mut ClnRpc, kind: &str) -> anyhow::Result<Vec<T>> {
+    let res = client
+        .call(Request::ListDatastore(ListdatastoreRequest {
+            key: Some(vec![DATASTORE_PLUGIN_KEY.to_string(), kind.to_string()]),
+        }))
+        .await?;
+
+    let datastore = match res {
+        Response::ListDatastore(l) => l.datastore,
+        _ => {
+            bail!("Invalid response");
+        }
+    };
+
+    let mut values = Vec::new();
+
+    for entry in datastore {
+        let value = match entry.string {
+            Some(value) => value,
+            None => continue,
+        };
+
+        match serde_json::from_str::<T>(&value) {
+            Ok(value) => values.push(value),
+            Err(e) => {
+                log::warn!("Skipping unreadable entry {:?}: {}", entry.key, e);
+            }
+        }
+    }
+
+    Ok(values)
+}
@@ -1,12 +1,48 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 mod client;
 mod constants;
+mod datastore;
+mod server;
 mod subscribe_to_messages;
 
-use client::lsps1_client::lsps1_client;
-use cln_plugin::{Builder, Error};
-use constants::PluginMethodState;
+use client::{lsps1_client::lsps1_client, poll_orders::Lsps1PollOrders};
+use cln_plugin::{
+    messages::{Configuration, NotificationTopic},
+    options::{ConfigOption, Value},
+    Builder, Error,
+};
+use cln_rpc::{primitives::PublicKey, ClnRpc};
+use constants::{
+    GetInfoJsonRpcResponseResult, HeldPayment, Lsps1Order, Lsps1ServerOrder, PendingRequest,
+    PendingRequestKey, LSPS1_AUTO_PAY_MAX_SAT_OPTION, LSPS1_CHANNEL_EXPIRY_BLOCKS_OPTION,
+    LSPS1_CONFIRM_PAYMENT_OPTION, LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS,
+    LSPS1_LEASE_EXPIRING_NOTIFICATION, LSPS1_LEASE_WARNING_BLOCKS,
+    LSPS1_LEASE_WARNING_BLOCKS_OPTION, LSPS1_LSP_MAX_FEE_SAT_OPTION, LSPS1_MAX_FEE_PAID,
+    LSPS1_MAX_FEE_PPM_OPTION, LSPS1_MAX_FEE_SAT_OPTION, LSPS1_ORDER_COMPLETED_NOTIFICATION,
+    LSPS1_ORDER_CREATED_NOTIFICATION, LSPS1_ORDER_FAILED_NOTIFICATION,
+    LSPS1_ORDER_PAID_NOTIFICATION, LSPS1_ORDER_REFUNDED_NOTIFICATION, LSPS1_POLL_INTERVAL_OPTION,
+    LSPS1_POLL_INTERVAL_SECS, LSPS1_REFUND_OVERDUE_NOTIFICATION, LSPS1_REFUND_TIMEOUT_OPTION,
+    LSPS1_REFUND_TIMEOUT_SECS, LSPS1_RESPONSE_TIMEOUT_OPTION, LSPS1_RESPONSE_TIMEOUT_SECS,
+    LSPS1_SERVER_ALLOW_OPTION, LSPS1_SERVER_BASE_FEE_SAT, LSPS1_SERVER_BASE_FEE_SAT_OPTION,
+    LSPS1_SERVER_DENY_OPTION, LSPS1_SERVER_FAST_CONFIRM_BLOCKS,
+    LSPS1_SERVER_FAST_CONFIRM_BLOCKS_OPTION, LSPS1_SERVER_FAST_CONFIRM_FEE_SAT_OPTION,
+    LSPS1_SERVER_FEE_PPM_OPTION, LSPS1_SERVER_LEASE_FEE_PPB_OPTION,
+    LSPS1_SERVER_MAX_CHANNEL_EXPIRY_BLOCKS_OPTION, LSPS1_SERVER_MAX_CLIENT_BALANCE_SAT_OPTION,
+    LSPS1_SERVER_MAX_LSP_BALANCE_SAT, LSPS1_SERVER_MAX_LSP_BALANCE_SAT_OPTION,
+    LSPS1_SERVER_MAX_OPEN_ORDERS, LSPS1_SERVER_MAX_OPEN_ORDERS_OPTION,
+    LSPS1_SERVER_MAX_ORDERS_PER_HOUR, LSPS1_SERVER_MAX_ORDERS_PER_HOUR_OPTION,
+    LSPS1_SERVER_MIN_CONNECTED_SECS_OPTION, LSPS1_SERVER_MIN_LSP_BALANCE_SAT,
+    LSPS1_SERVER_MIN_LSP_BALANCE_SAT_OPTION, LSPS1_SERVER_OPTION,
+    LSPS1_SERVER_REQUIRE_PUBLIC_CHANNEL_OPTION, LSPS1_SERVER_WEBSITE_OPTION,
+};
+use datastore::{load_orders, load_server_orders};
+use server::{
+    admission::{peer_connected, peer_disconnected},
+    hold_invoice::htlc_accepted,
+    inventory::lsps1_server_inventory,
+    list_orders::lsps1_server_orders,
+};
 
 use tokio::{
     io::{stdin, stdout},
@@ -16,37 +52,222 @@ use tokio::{
 use subscribe_to_messages::subscribe_to_custom_message;
 
 struct PluginState {
-    data: Mutex<HashMap<String, String>>,
-    method: Mutex<PluginMethodState>,
+    data: Mutex<HashMap<String, Lsps1Order>>,
+    pending: Mutex<HashMap<PendingRequestKey, PendingRequest>>,
+    info: Mutex<HashMap<PublicKey, (u64, GetInfoJsonRpcResponseResult)>>,
+    server_orders: Mutex<HashMap<String, Lsps1ServerOrder>>,
+    held: Mutex<HashMap<String, HeldPayment>>,
+    order_requests: Mutex<HashMap<PublicKey, Vec<u64>>>,
+    connected: Mutex<HashMap<PublicKey, u64>>,
+    reserving: Mutex<()>,
 }
 
 impl PluginState {
-    async fn new() -> Result<Self, Error> {
+    async fn new(conf: &Configuration) -> Result<Self, Error> {
+        // Pick up the orders from before the last restart
+        let socket_path = Path::new(&conf.lightning_dir).join(&conf.rpc_file);
+        let mut client = ClnRpc::new(socket_path).await?;
+
+        let orders = load_orders(&mut client).await?;
+        log::info!("Loaded {} orders from the datastore", orders.len());
+
+        let server_orders = load_server_orders(&mut client).await?;
+        log::info!(
+            "Loaded {} server orders from the datastore",
+            server_orders.len()
+        );
+
         Ok(Self {
-            data: Mutex::new(HashMap::new()),
-            method: Mutex::new(PluginMethodState::None),
+            data: Mutex::new(orders),
+            pending: Mutex::new(HashMap::new()),
+            info: Mutex::new(HashMap::new()),
+            server_orders: Mutex::new(server_orders),
+            held: Mutex::new(HashMap::new()),
+            order_requests: Mutex::new(HashMap::new()),
+            connected: Mutex::new(HashMap::new()),
+            reserving: Mutex::new(()),
         })
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let plugin_state = Arc::new(PluginState::new().await?);
-
-    if let Some(plugin) = Builder::new(stdin(), stdout())
+    if let Some(configured) = Builder::new(stdin(), stdout())
         .dynamic()
+        .option(ConfigOption::new(
+            LSPS1_RESPONSE_TIMEOUT_OPTION,
+            Value::Integer(LSPS1_RESPONSE_TIMEOUT_SECS as i64),
+            "Seconds to wait for a response from the LSP",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_MAX_FEE_SAT_OPTION,
+            Value::Integer(LSPS1_MAX_FEE_PAID as i64),
+            "Most sats to pay an LSP for a channel",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_MAX_FEE_PPM_OPTION,
+            Value::Integer(0),
+            "Most to pay an LSP in ppm of the channel size, 0 disables the check",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_LSP_MAX_FEE_SAT_OPTION,
+            Value::OptString,
+            "Per LSP fee limits in sats that replace lsps1-max-fee-sat, as pubkey:sat,pubkey:sat",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_CONFIRM_PAYMENT_OPTION,
+            Value::Boolean(false),
+            "Only create and validate orders on buy, pay them with method=payorder",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_AUTO_PAY_MAX_SAT_OPTION,
+            Value::Integer(0),
+            "Orders up to this many sats are still paid right away when lsps1-confirm-payment is set",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_POLL_INTERVAL_OPTION,
+            Value::Integer(LSPS1_POLL_INTERVAL_SECS as i64),
+            "Seconds between checking on open orders, 0 disables polling",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_CHANNEL_EXPIRY_BLOCKS_OPTION,
+            Value::Integer(LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS as i64),
+            "Blocks the LSP has to keep a bought channel open, when buy is not given an expiry",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_LEASE_WARNING_BLOCKS_OPTION,
+            Value::Integer(LSPS1_LEASE_WARNING_BLOCKS as i64),
+            "Warn this many blocks before a bought channel's lease ends",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_REFUND_TIMEOUT_OPTION,
+            Value::Integer(LSPS1_REFUND_TIMEOUT_SECS as i64),
+            "Seconds to wait for the refund of a failed order before warning about it",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_OPTION,
+            Value::Boolean(false),
+            "Sell inbound channels to peers over LSPS1",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MIN_LSP_BALANCE_SAT_OPTION,
+            Value::Integer(LSPS1_SERVER_MIN_LSP_BALANCE_SAT as i64),
+            "Smallest channel balance in sats we sell as the LSP",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MAX_LSP_BALANCE_SAT_OPTION,
+            Value::Integer(LSPS1_SERVER_MAX_LSP_BALANCE_SAT as i64),
+            "Largest channel balance in sats we sell as the LSP",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MAX_CLIENT_BALANCE_SAT_OPTION,
+            Value::Integer(0),
+            "Most sats we push to the client's side of a channel we sell",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MAX_CHANNEL_EXPIRY_BLOCKS_OPTION,
+            Value::Integer(LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS as i64),
+            "Longest lease in blocks we sell channels for",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_BASE_FEE_SAT_OPTION,
+            Value::Integer(LSPS1_SERVER_BASE_FEE_SAT as i64),
+            "Fee in sats we charge for every channel we sell",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_FEE_PPM_OPTION,
+            Value::Integer(0),
+            "Fee in ppm of the channel balance we charge on top of the base fee",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_LEASE_FEE_PPB_OPTION,
+            Value::Integer(0),
+            "Fee in parts per billion of the channel balance we charge for every block of the lease",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_FAST_CONFIRM_BLOCKS_OPTION,
+            Value::Integer(LSPS1_SERVER_FAST_CONFIRM_BLOCKS as i64),
+            "Orders that want the channel confirmed within fewer blocks pay the fast confirm fee",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_FAST_CONFIRM_FEE_SAT_OPTION,
+            Value::Integer(0),
+            "Extra fee in sats for orders that want the channel confirmed fast",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_WEBSITE_OPTION,
+            Value::OptString,
+            "Website we send to clients in lsps1.get_info",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_ALLOW_OPTION,
+            Value::OptString,
+            "Only these nodes can place orders with us, as pubkey,pubkey",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_DENY_OPTION,
+            Value::OptString,
+            "These nodes can never place orders with us, as pubkey,pubkey",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MAX_ORDERS_PER_HOUR_OPTION,
+            Value::Integer(LSPS1_SERVER_MAX_ORDERS_PER_HOUR as i64),
+            "Most orders a node can try to place with us per hour, 0 disables the limit",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MAX_OPEN_ORDERS_OPTION,
+            Value::Integer(LSPS1_SERVER_MAX_OPEN_ORDERS as i64),
+            "Most unfinished orders a node can have with us, 0 disables the limit",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_REQUIRE_PUBLIC_CHANNEL_OPTION,
+            Value::Boolean(false),
+            "Only take orders from nodes with a public channel",
+        ))
+        .option(ConfigOption::new(
+            LSPS1_SERVER_MIN_CONNECTED_SECS_OPTION,
+            Value::Integer(0),
+            "Seconds a node has to be connected to us before it can place an order",
+        ))
+        .notification(NotificationTopic::new(LSPS1_ORDER_CREATED_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_ORDER_PAID_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_ORDER_COMPLETED_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_ORDER_FAILED_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_LEASE_EXPIRING_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_ORDER_REFUNDED_NOTIFICATION))
+        .notification(NotificationTopic::new(LSPS1_REFUND_OVERDUE_NOTIFICATION))
         .rpcmethod(
             "buy-inbound-channel",
             "Buy an inbound channel from other peers",
             lsps1_client,
         )
+        .rpcmethod(
+            "lsps1-server-orders",
+            "List the orders clients placed with us and how they were priced",
+            lsps1_server_orders,
+        )
+        .rpcmethod(
+            "lsps1-server-inventory",
+            "Show how much of our on-chain funds are reserved for orders",
+            lsps1_server_inventory,
+        )
         .hook("custommsg", subscribe_to_custom_message)
-        .start(plugin_state)
+        .hook("htlc_accepted", htlc_accepted)
+        .subscribe("connect", peer_connected)
+        .subscribe("disconnect", peer_disconnected)
+        .configure()
         .await?
     {
-        let plug_res = plugin.join().await;
+        let plugin_state = Arc::new(PluginState::new(&configured.configuration()).await?);
+
+        let plugin = configured.start(plugin_state).await?;
+
+        let poller = Lsps1PollOrders {
+            plugin: plugin.clone(),
+        };
+        tokio::spawn(async move { poller.run().await });
 
-        plug_res
+        plugin.join().await
     } else {
         Ok(())
     }
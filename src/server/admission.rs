@@ -0,0 +1,223 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::bail;
+use cln_plugin::{options::Value, Error, Plugin};
+use cln_rpc::{
+    model::requests::ListchannelsRequest, primitives::PublicKey, ClnRpc, Request, Response,
+};
+
+use crate::{
+    client::utils::unix_timestamp,
+    constants::{
+        JsonRpcError, Lsps0ErrorCode, OrderState, LSPS1_SERVER_ALLOW_OPTION,
+        LSPS1_SERVER_DENY_OPTION, LSPS1_SERVER_MAX_OPEN_ORDERS,
+        LSPS1_SERVER_MAX_OPEN_ORDERS_OPTION, LSPS1_SERVER_MAX_ORDERS_PER_HOUR,
+        LSPS1_SERVER_MAX_ORDERS_PER_HOUR_OPTION, LSPS1_SERVER_MIN_CONNECTED_SECS_OPTION,
+        LSPS1_SERVER_REQUIRE_PUBLIC_CHANNEL_OPTION,
+    },
+    PluginState,
+};
+
+use super::utils::{internal_error, option_u64};
+
+// Decide whether a peer gets to place an order with us
+pub struct Lsps1ServerAdmission {
+    pub client: ClnRpc,
+    pub peer_id: PublicKey,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerAdmission {
+    pub async fn admit(&mut self) -> Result<(), JsonRpcError> {
+        let allow =
+            parse_pubkeys(&self.plugin, LSPS1_SERVER_ALLOW_OPTION).map_err(internal_error)?;
+        let deny = parse_pubkeys(&self.plugin, LSPS1_SERVER_DENY_OPTION).map_err(internal_error)?;
+
+        if deny.contains(&self.peer_id) {
+            return Err(rejected("Node is not allowed to place orders"));
+        }
+
+        if !allow.is_empty() && !allow.contains(&self.peer_id) {
+            return Err(rejected("Node is not allowed to place orders"));
+        }
+
+        self.check_rate_limit().await?;
+        self.check_connected_time().await?;
+
+        if matches!(
+            self.plugin
+                .option(LSPS1_SERVER_REQUIRE_PUBLIC_CHANNEL_OPTION),
+            Some(Value::Boolean(true))
+        ) && !self.has_public_channel().await.map_err(internal_error)?
+        {
+            return Err(rejected("Node needs a public channel to place orders"));
+        }
+
+        Ok(())
+    }
+
+    // Every create_order counts, even the ones we turn down
+    async fn check_rate_limit(&self) -> Result<(), JsonRpcError> {
+        let max_per_hour = option_u64(
+            &self.plugin,
+            LSPS1_SERVER_MAX_ORDERS_PER_HOUR_OPTION,
+            LSPS1_SERVER_MAX_ORDERS_PER_HOUR,
+        );
+
+        if max_per_hour == 0 {
+            return Ok(());
+        }
+
+        let now = unix_timestamp();
+        let mut requests = self.plugin.state().order_requests.lock().await;
+
+        let times = requests.entry(self.peer_id).or_default();
+        times.retain(|time| now.saturating_sub(*time) < 3600);
+
+        if times.len() as u64 >= max_per_hour {
+            return Err(rejected("Too many orders, try again later"));
+        }
+
+        times.push(now);
+
+        Ok(())
+    }
+
+    async fn check_connected_time(&self) -> Result<(), JsonRpcError> {
+        let min_connected_secs =
+            option_u64(&self.plugin, LSPS1_SERVER_MIN_CONNECTED_SECS_OPTION, 0);
+
+        if min_connected_secs == 0 {
+            return Ok(());
+        }
+
+        let now = unix_timestamp();
+
+        // Peers that connected before we started count from when we first see them
+        let connected_at = *self
+            .plugin
+            .state()
+            .connected
+            .lock()
+            .await
+            .entry(self.peer_id)
+            .or_insert(now);
+
+        if now.saturating_sub(connected_at) < min_connected_secs {
+            return Err(rejected("Node has not been connected long enough"));
+        }
+
+        Ok(())
+    }
+
+    // A node with a public channel announces it in the gossip we have
+    async fn has_public_channel(&mut self) -> anyhow::Result<bool> {
+        let res = self
+            .client
+            .call(Request::ListChannels(ListchannelsRequest {
+                short_channel_id: None,
+                source: Some(self.peer_id),
+                destination: None,
+            }))
+            .await?;
+
+        match res {
+            Response::ListChannels(n) => Ok(n.channels.iter().any(|c| c.public)),
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+}
+
+// Callers hold the reserving lock, otherwise two orders could both get in under the limit
+pub async fn check_open_orders(
+    plugin: &Plugin<Arc<PluginState>>,
+    peer_id: &PublicKey,
+) -> Result<(), JsonRpcError> {
+    let max_open_orders = option_u64(
+        plugin,
+        LSPS1_SERVER_MAX_OPEN_ORDERS_OPTION,
+        LSPS1_SERVER_MAX_OPEN_ORDERS,
+    );
+
+    if max_open_orders == 0 {
+        return Ok(());
+    }
+
+    let now = unix_timestamp();
+
+    // Unpaid orders that ran out don't count, they just haven't been cleaned up yet
+    let open_orders = plugin
+        .state()
+        .server_orders
+        .lock()
+        .await
+        .values()
+        .filter(|order| {
+            order.client == *peer_id
+                && order.order.order_state == OrderState::Created
+                && !order.is_expired(now)
+        })
+        .count();
+
+    if open_orders as u64 >= max_open_orders {
+        return Err(rejected("Too many open orders"));
+    }
+
+    Ok(())
+}
+
+// Remember when peers connect so we know how long they've been around
+pub async fn peer_connected(
+    p: Plugin<Arc<PluginState>>,
+    v: serde_json::Value,
+) -> Result<(), Error> {
+    if let Some(peer_id) = peer_id(&v, "connect") {
+        p.state()
+            .connected
+            .lock()
+            .await
+            .insert(peer_id, unix_timestamp());
+    }
+
+    Ok(())
+}
+
+pub async fn peer_disconnected(
+    p: Plugin<Arc<PluginState>>,
+    v: serde_json::Value,
+) -> Result<(), Error> {
+    if let Some(peer_id) = peer_id(&v, "disconnect") {
+        p.state().connected.lock().await.remove(&peer_id);
+    }
+
+    Ok(())
+}
+
+// Newer nodes wrap the notification in an object named after it
+fn peer_id(v: &serde_json::Value, topic: &str) -> Option<PublicKey> {
+    v[topic]["id"]
+        .as_str()
+        .or_else(|| v["id"].as_str())
+        .and_then(|id| PublicKey::from_str(id).ok())
+}
+
+fn parse_pubkeys(plugin: &Plugin<Arc<PluginState>>, name: &str) -> anyhow::Result<Vec<PublicKey>> {
+    let list = match plugin.option(name) {
+        Some(Value::String(list)) => list,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut pubkeys = Vec::new();
+
+    for entry in list.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        pubkeys.push(PublicKey::from_str(entry)?);
+    }
+
+    Ok(pubkeys)
+}
+
+fn rejected(message: &str) -> JsonRpcError {
+    JsonRpcError::new(Lsps0ErrorCode::ClientRejected, message)
+}
@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::Plugin;
+use cln_rpc::{
+    model::requests::InvoiceRequest,
+    primitives::{Amount, AmountOrAny, PublicKey},
+    ClnRpc, Request, Response,
+};
+
+use crate::{
+    client::utils::{make_client, make_id, unix_timestamp},
+    constants::{
+        CreateOrderJsonRpcRequestParams, CreateOrderJsonRpcResponsePayment,
+        CreateOrderJsonRpcResponseResult, GetInfoJsonRpcResponseOptions, JsonRpcError,
+        Lsps0ErrorCode, Lsps1ServerOrder, OrderState, PaymentState,
+        LSPS1_SERVER_INVOICE_LABEL_PREFIX, LSPS1_SERVER_ORDER_EXPIRY_SECS,
+    },
+    datastore::save_server_order,
+    PluginState,
+};
+
+use super::{
+    admission::check_open_orders,
+    get_info::Lsps1ServerGetInfo,
+    inventory::Lsps1ServerInventory,
+    pricing::Lsps1ServerPricing,
+    utils::{internal_error, iso8601},
+};
+
+pub struct Lsps1ServerCreateOrder {
+    pub client: ClnRpc,
+    pub peer_id: PublicKey,
+    pub params: CreateOrderJsonRpcRequestParams,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerCreateOrder {
+    pub async fn create_order(&mut self) -> Result<CreateOrderJsonRpcResponseResult, JsonRpcError> {
+        let info = Lsps1ServerGetInfo {
+            plugin: self.plugin.clone(),
+        }
+        .get_info();
+
+        let (lsp_balance_sat, client_balance_sat) = self.check_params(&info.options)?;
+
+        // One order at a time so two of them can't be promised the same funds
+        let plugin = self.plugin.clone();
+        let _reserving = plugin.state().reserving.lock().await;
+
+        // Nothing gets added to server_orders while we hold the lock, so the count holds until we insert
+        check_open_orders(&self.plugin, &self.peer_id).await?;
+
+        let capital = Lsps1ServerInventory {
+            client: make_client(&self.plugin).await.map_err(internal_error)?,
+            plugin: self.plugin.clone(),
+        }
+        .capital()
+        .await
+        .map_err(internal_error)?;
+
+        if capital.available_sat < lsp_balance_sat + client_balance_sat {
+            log::warn!(
+                "Not enough capital for a {} sat channel to {}, {} sat available",
+                lsp_balance_sat + client_balance_sat,
+                self.peer_id,
+                capital.available_sat
+            );
+
+            return Err(JsonRpcError::option_mismatch("lsp_balance_sat"));
+        }
+
+        let fee_breakdown = Lsps1ServerPricing::from_options(&self.plugin)
+            .price(
+                &mut self.client,
+                lsp_balance_sat,
+                self.params.channel_expiry_blocks,
+                self.params.confirms_within_blocks,
+            )
+            .await
+            .map_err(internal_error)?;
+
+        let fee_total_sat = fee_breakdown.fee_total_sat;
+
+        // The client pays for whatever we push to their side too
+        let order_total_sat = fee_total_sat
+            .checked_add(client_balance_sat)
+            .ok_or_else(|| JsonRpcError::option_mismatch("client_balance_sat"))?;
+
+        let order_id = make_id();
+        let label = format!("{}{}", LSPS1_SERVER_INVOICE_LABEL_PREFIX, order_id);
+
+        let now = unix_timestamp();
+        let expires_at = now + LSPS1_SERVER_ORDER_EXPIRY_SECS;
+
+        let (lightning_invoice, payment_hash, payment_secret) = self
+            .make_invoice(&label, &order_id, order_total_sat)
+            .await
+            .map_err(internal_error)?;
+
+        let order = CreateOrderJsonRpcResponseResult {
+            order_id: order_id.clone(),
+            lsp_balance_sat: lsp_balance_sat.to_string(),
+            client_balance_sat: client_balance_sat.to_string(),
+            confirms_within_blocks: self.params.confirms_within_blocks,
+            channel_expiry_blocks: self.params.channel_expiry_blocks,
+            token: self.params.token.clone(),
+            created_at: iso8601(now),
+            expires_at: iso8601(expires_at),
+            announce_channel: self.params.announce_channel,
+            order_state: OrderState::Created,
+            payment: CreateOrderJsonRpcResponsePayment {
+                state: PaymentState::ExpectPayment,
+                fee_total_sat: fee_total_sat.to_string(),
+                order_total_sat: order_total_sat.to_string(),
+                lightning_invoice,
+                onchain_address: None,
+                min_onchain_payment_confirmations: None,
+                min_fee_for_0conf: None,
+                onchain_payment: None,
+            },
+            channel: None,
+        };
+
+        let server_order = Lsps1ServerOrder {
+            client: self.peer_id,
+            order: order.clone(),
+            label,
+            payment_hash: Some(payment_hash),
+            payment_secret: Some(payment_secret),
+            fee_breakdown: Some(fee_breakdown),
+            error: None,
+            expires_at,
+            created_at: now,
+            updated_at: now,
+        };
+
+        save_server_order(&mut self.client, &server_order)
+            .await
+            .map_err(internal_error)?;
+
+        self.plugin
+            .state()
+            .server_orders
+            .lock()
+            .await
+            .insert(order_id.clone(), server_order);
+
+        log::info!(
+            "Created order {} for {}, {} sat channel for {} sat",
+            order_id,
+            self.peer_id,
+            lsp_balance_sat + client_balance_sat,
+            order_total_sat
+        );
+
+        Ok(order)
+    }
+
+    // Turn down anything outside what we advertise in get_info
+    fn check_params(
+        &self,
+        options: &GetInfoJsonRpcResponseOptions,
+    ) -> Result<(u64, u64), JsonRpcError> {
+        let lsp_balance_sat = parse_sat(&self.params.lsp_balance_sat)?;
+        let client_balance_sat = parse_sat(&self.params.client_balance_sat)?;
+
+        if lsp_balance_sat < parse_sat(&options.min_initial_lsp_balance_sat)?
+            || lsp_balance_sat > parse_sat(&options.max_initial_lsp_balance_sat)?
+        {
+            return Err(JsonRpcError::option_mismatch("lsp_balance_sat"));
+        }
+
+        if client_balance_sat < parse_sat(&options.min_initial_client_balance_sat)?
+            || client_balance_sat > parse_sat(&options.max_initial_client_balance_sat)?
+        {
+            return Err(JsonRpcError::option_mismatch("client_balance_sat"));
+        }
+
+        if self.params.channel_expiry_blocks == 0
+            || self.params.channel_expiry_blocks > options.max_channel_expiry_blocks
+        {
+            return Err(JsonRpcError::option_mismatch("channel_expiry_blocks"));
+        }
+
+        Ok((lsp_balance_sat, client_balance_sat))
+    }
+
+    async fn make_invoice(
+        &mut self,
+        label: &str,
+        order_id: &str,
+        order_total_sat: u64,
+    ) -> anyhow::Result<(String, String, String)> {
+        let res = self
+            .client
+            .call(Request::Invoice(InvoiceRequest {
+                amount_msat: AmountOrAny::Amount(Amount::from_sat(order_total_sat)),
+                description: format!("LSPS1 order {}", order_id),
+                label: label.to_string(),
+                expiry: Some(LSPS1_SERVER_ORDER_EXPIRY_SECS),
+                fallbacks: None,
+                preimage: None,
+                cltv: None,
+                deschashonly: None,
+            }))
+            .await?;
+
+        match res {
+            Response::Invoice(n) => Ok((
+                n.bolt11,
+                n.payment_hash.to_string(),
+                hex::encode(n.payment_secret.to_vec()),
+            )),
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+}
+
+fn parse_sat(sat: &str) -> Result<u64, JsonRpcError> {
+    sat.parse()
+        .map_err(|_| JsonRpcError::new(Lsps0ErrorCode::InvalidParams, "Invalid sat amount"))
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use cln_plugin::{options::Value, Plugin};
+
+use crate::{
+    constants::{
+        GetInfoJsonRpcResponseOptions, GetInfoJsonRpcResponseResult,
+        LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS, LSPS1_SERVER_MAX_CHANNEL_EXPIRY_BLOCKS_OPTION,
+        LSPS1_SERVER_MAX_CLIENT_BALANCE_SAT_OPTION, LSPS1_SERVER_MAX_LSP_BALANCE_SAT,
+        LSPS1_SERVER_MAX_LSP_BALANCE_SAT_OPTION, LSPS1_SERVER_MIN_CHANNEL_CONFIRMATIONS,
+        LSPS1_SERVER_MIN_LSP_BALANCE_SAT, LSPS1_SERVER_MIN_LSP_BALANCE_SAT_OPTION,
+        LSPS1_SERVER_WEBSITE_OPTION,
+    },
+    PluginState,
+};
+
+use super::utils::option_u64;
+
+pub struct Lsps1ServerGetInfo {
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerGetInfo {
+    // What we sell is all in the plugin options
+    pub fn get_info(&self) -> GetInfoJsonRpcResponseResult {
+        let min_lsp_balance_sat = option_u64(
+            &self.plugin,
+            LSPS1_SERVER_MIN_LSP_BALANCE_SAT_OPTION,
+            LSPS1_SERVER_MIN_LSP_BALANCE_SAT,
+        );
+        let max_lsp_balance_sat = option_u64(
+            &self.plugin,
+            LSPS1_SERVER_MAX_LSP_BALANCE_SAT_OPTION,
+            LSPS1_SERVER_MAX_LSP_BALANCE_SAT,
+        );
+        let max_client_balance_sat =
+            option_u64(&self.plugin, LSPS1_SERVER_MAX_CLIENT_BALANCE_SAT_OPTION, 0);
+        let max_channel_expiry_blocks = option_u64(
+            &self.plugin,
+            LSPS1_SERVER_MAX_CHANNEL_EXPIRY_BLOCKS_OPTION,
+            LSPS1_CREATE_ORDER_CHANNEL_EXPIRY_BLOCKS as u64,
+        );
+
+        let website = match self.plugin.option(LSPS1_SERVER_WEBSITE_OPTION) {
+            Some(Value::String(website)) => website,
+            _ => String::new(),
+        };
+
+        GetInfoJsonRpcResponseResult {
+            options: GetInfoJsonRpcResponseOptions {
+                min_channel_confirmations: LSPS1_SERVER_MIN_CHANNEL_CONFIRMATIONS,
+                min_onchain_payment_confirmations: None,
+                supports_zero_channel_reserve: false,
+                min_onchain_payment_size_sat: None,
+                max_channel_expiry_blocks: max_channel_expiry_blocks as u32,
+                min_initial_client_balance_sat: "0".to_string(),
+                max_initial_client_balance_sat: max_client_balance_sat.to_string(),
+                min_initial_lsp_balance_sat: min_lsp_balance_sat.to_string(),
+                max_initial_lsp_balance_sat: max_lsp_balance_sat.to_string(),
+                min_channel_balance_sat: min_lsp_balance_sat.to_string(),
+                max_channel_balance_sat: (max_lsp_balance_sat + max_client_balance_sat).to_string(),
+            },
+            website,
+        }
+    }
+}
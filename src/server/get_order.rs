@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use cln_plugin::Plugin;
+use cln_rpc::{primitives::PublicKey, ClnRpc};
+
+use crate::{
+    client::utils::unix_timestamp,
+    constants::{CreateOrderJsonRpcResponseResult, JsonRpcError, Lsps0ErrorCode, OrderState},
+    datastore::save_server_order,
+    PluginState,
+};
+
+use super::utils::internal_error;
+
+pub struct Lsps1ServerGetOrder {
+    pub client: ClnRpc,
+    pub peer_id: PublicKey,
+    pub order_id: String,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerGetOrder {
+    pub async fn get_order(&mut self) -> Result<CreateOrderJsonRpcResponseResult, JsonRpcError> {
+        let mut orders = self.plugin.state().server_orders.lock().await;
+
+        // Clients only get to see their own orders
+        let order = match orders.get_mut(&self.order_id) {
+            Some(order) if order.client == self.peer_id => order,
+            _ => {
+                return Err(JsonRpcError::new(
+                    Lsps0ErrorCode::NotFound,
+                    "Order not found",
+                ))
+            }
+        };
+
+        // Nobody paid before the invoice ran out
+        let now = unix_timestamp();
+
+        if order.is_expired(now) {
+            log::info!("Order {} expired without payment", self.order_id);
+
+            order.order.order_state = OrderState::Failed;
+            order.error = Some("Order expired without payment".to_string());
+            order.updated_at = now;
+
+            save_server_order(&mut self.client, order)
+                .await
+                .map_err(internal_error)?;
+        }
+
+        Ok(order.order.clone())
+    }
+}
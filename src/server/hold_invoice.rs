@@ -0,0 +1,264 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::bail;
+use cln_plugin::{Error, Plugin};
+use cln_rpc::{
+    model::{requests::ListinvoicesRequest, responses::ListinvoicesInvoicesStatus},
+    ClnRpc, Request, Response,
+};
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::{
+    client::utils::{block_height, make_client, unix_timestamp},
+    constants::{HeldPayment, OrderState, PaymentState, LSPS1_SERVER_HOLD_TIMEOUT_SECS},
+    PluginState,
+};
+
+use super::{open_channel::Lsps1ServerOpenChannel, utils::server_enabled};
+
+// Hold payments for our orders until the channel is open
+// Settle them once it is, fail them back if it couldn't be opened
+pub async fn htlc_accepted(
+    p: Plugin<Arc<PluginState>>,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    if !server_enabled(&p) {
+        return Ok(json!({ "result": "continue" }));
+    }
+
+    let payment_hash = match v["htlc"]["payment_hash"].as_str() {
+        Some(payment_hash) => payment_hash,
+        None => return Ok(json!({ "result": "continue" })),
+    };
+
+    // Being forwarded, so it isn't paying us
+    if v["onion"]["short_channel_id"].is_string() {
+        return Ok(json!({ "result": "continue" }));
+    }
+
+    let amount_msat = match msat(&v["htlc"]["amount_msat"]) {
+        Some(amount_msat) => amount_msat,
+        None => return Ok(json!({ "result": "continue" })),
+    };
+
+    let (order_id, order_total_msat, label, payment_secret, expired) = {
+        let orders = p.state().server_orders.lock().await;
+
+        let order = orders
+            .values()
+            .find(|order| order.payment_hash.as_deref() == Some(payment_hash));
+
+        match order {
+            // Don't take money for an order we already gave up on
+            Some(order) if order.order.order_state == OrderState::Failed => return Ok(fail()),
+            Some(order) => match order.order.payment.state {
+                PaymentState::ExpectPayment | PaymentState::Hold => (
+                    order.order.order_id.clone(),
+                    order.order.payment.order_total_sat.parse::<u64>()? * 1000,
+                    order.label.clone(),
+                    order.payment_secret.clone(),
+                    order.is_expired(unix_timestamp()),
+                ),
+                PaymentState::Paid => return Ok(json!({ "result": "continue" })),
+                PaymentState::Refunded => return Ok(fail()),
+            },
+            // Not one of ours
+            None => return Ok(json!({ "result": "continue" })),
+        }
+    };
+
+    let mut client = match make_client(&p).await {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Failed to check payment for order {}: {}", order_id, e);
+            return Ok(fail());
+        }
+    };
+
+    // lightningd only checks the invoice after we let the payment through,
+    // and we open the channel before that, so check it all ourselves first
+    let invalid = if expired {
+        Ok(Some("the order expired".to_string()))
+    } else {
+        check_payment(
+            &mut client,
+            &v["onion"],
+            amount_msat,
+            order_total_msat,
+            &label,
+            payment_secret.as_deref(),
+        )
+        .await
+    };
+
+    match invalid {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            log::warn!(
+                "Failing part of {} msat for order {}, {}",
+                amount_msat,
+                order_id,
+                reason
+            );
+
+            return Ok(match block_height(&mut client).await {
+                Ok(height) => unknown_payment(amount_msat, height),
+                Err(_) => fail(),
+            });
+        }
+        Err(e) => {
+            log::error!("Failed to check payment for order {}: {}", order_id, e);
+            return Ok(fail());
+        }
+    }
+
+    // Add this part to what we're holding, the one that completes the payment opens the channel
+    let (mut receiver, complete) = {
+        let mut held = p.state().held.lock().await;
+
+        let payment = held.entry(order_id.clone()).or_insert_with(|| HeldPayment {
+            amount_msat: 0,
+            opening: false,
+            result: watch::channel(None).0,
+        });
+
+        payment.amount_msat += amount_msat;
+
+        let complete = !payment.opening && payment.amount_msat >= order_total_msat;
+
+        if complete {
+            payment.opening = true;
+        }
+
+        (payment.result.subscribe(), complete)
+    };
+
+    if complete {
+        let opened = Lsps1ServerOpenChannel {
+            client,
+            order_id: order_id.clone(),
+            plugin: p.clone(),
+        }
+        .open_channel()
+        .await;
+
+        let opened = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                log::error!("Failed to open channel for order {}: {}", order_id, e);
+                false
+            }
+        };
+
+        // Let every held part know how it went
+        if let Some(payment) = p.state().held.lock().await.remove(&order_id) {
+            let _ = payment.result.send(Some(opened));
+        }
+    }
+
+    let res = tokio::time::timeout(
+        Duration::from_secs(LSPS1_SERVER_HOLD_TIMEOUT_SECS),
+        receiver.wait_for(|opened| opened.is_some()),
+    )
+    .await
+    .map(|opened| opened.is_ok_and(|opened| *opened == Some(true)));
+
+    match res {
+        Ok(true) => Ok(json!({ "result": "continue" })),
+        Ok(false) => Ok(fail()),
+        Err(_) => {
+            log::warn!(
+                "Payment for order {} didn't complete in time, failing part of {} msat back",
+                order_id,
+                amount_msat
+            );
+
+            // Stop counting this part towards the order
+            if let Some(payment) = p.state().held.lock().await.get_mut(&order_id) {
+                if !payment.opening {
+                    payment.amount_msat = payment.amount_msat.saturating_sub(amount_msat);
+                }
+            }
+
+            Ok(fail())
+        }
+    }
+}
+
+// Returns why this part of the payment can't be for the order's invoice, if it can't
+async fn check_payment(
+    client: &mut ClnRpc,
+    onion: &serde_json::Value,
+    amount_msat: u64,
+    order_total_msat: u64,
+    label: &str,
+    payment_secret: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    match (onion["payment_secret"].as_str(), payment_secret) {
+        (Some(secret), Some(expected)) if secret == expected => {}
+        _ => return Ok(Some("the payment secret doesn't match".to_string())),
+    }
+
+    // Single part payments don't have to say what the total is
+    let total_msat = msat(&onion["total_msat"]).unwrap_or(amount_msat);
+
+    if total_msat < order_total_msat {
+        return Ok(Some(format!(
+            "the payment total of {} msat is below the order total of {} msat",
+            total_msat, order_total_msat
+        )));
+    }
+
+    let res = client
+        .call(Request::ListInvoices(ListinvoicesRequest {
+            label: Some(label.to_string()),
+            invstring: None,
+            payment_hash: None,
+            offer_id: None,
+            index: None,
+            start: None,
+            limit: None,
+        }))
+        .await?;
+
+    let invoice = match res {
+        Response::ListInvoices(n) => n.invoices.into_iter().next(),
+        _ => {
+            bail!("Invalid response");
+        }
+    };
+
+    match invoice {
+        Some(invoice) if invoice.expires_at <= unix_timestamp() => {
+            Ok(Some("the invoice expired".to_string()))
+        }
+        Some(invoice) => match invoice.status {
+            ListinvoicesInvoicesStatus::UNPAID => Ok(None),
+            _ => Ok(Some(format!("the invoice is {:?}", invoice.status))),
+        },
+        None => Ok(Some(format!("invoice {} not found", label))),
+    }
+}
+
+// Older nodes send amounts as strings like "1000msat"
+fn msat(amount: &serde_json::Value) -> Option<u64> {
+    match amount {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.trim_end_matches("msat").parse().ok(),
+        _ => None,
+    }
+}
+
+// incorrect_or_unknown_payment_details, what the client would get from lightningd
+fn unknown_payment(amount_msat: u64, height: u32) -> serde_json::Value {
+    json!({
+        "result": "fail",
+        "failure_message": format!("400f{:016x}{:08x}", amount_msat, height),
+    })
+}
+
+// temporary_node_failure, the client can try paying again
+fn fail() -> serde_json::Value {
+    json!({ "result": "fail", "failure_message": "2002" })
+}
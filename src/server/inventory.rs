@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::{Error, Plugin};
+use cln_rpc::{
+    model::{requests::ListfundsRequest, responses::ListfundsOutputsStatus},
+    ClnRpc, Request, Response,
+};
+use serde_json::json;
+
+use crate::{
+    client::utils::{make_client, unix_timestamp},
+    constants::{Lsps1ServerCapital, OrderState, PaymentState},
+    datastore::save_server_order,
+    PluginState,
+};
+
+// Keep track of the on-chain funds our orders will need
+// Orders waiting for payment reserve the channel they'll open, orders being opened are in flight
+pub struct Lsps1ServerInventory {
+    pub client: ClnRpc,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerInventory {
+    pub async fn capital(&mut self) -> anyhow::Result<Lsps1ServerCapital> {
+        self.release_expired().await?;
+
+        let wallet_sat = self.wallet_sat().await?;
+
+        let (committed_sat, in_flight_sat) = {
+            let orders = self.plugin.state().server_orders.lock().await;
+
+            orders
+                .values()
+                .filter(|order| order.order.order_state == OrderState::Created)
+                .fold((0, 0), |(committed, in_flight), order| {
+                    match order.order.payment.state {
+                        PaymentState::ExpectPayment => (committed + order.channel_sat(), in_flight),
+                        PaymentState::Hold => (committed, in_flight + order.channel_sat()),
+                        _ => (committed, in_flight),
+                    }
+                })
+        };
+
+        // Funds for channels being opened might still show up in the wallet for a moment,
+        // counting them twice is better than promising a channel we can't open
+        Ok(Lsps1ServerCapital {
+            wallet_sat,
+            committed_sat,
+            in_flight_sat,
+            available_sat: wallet_sat.saturating_sub(committed_sat + in_flight_sat),
+        })
+    }
+
+    // Orders nobody paid for in time give their funds back
+    pub async fn release_expired(&mut self) -> anyhow::Result<()> {
+        let now = unix_timestamp();
+        let mut orders = self.plugin.state().server_orders.lock().await;
+
+        for order in orders.values_mut().filter(|order| order.is_expired(now)) {
+            log::info!(
+                "Order {} expired without payment, releasing {} sat",
+                order.order.order_id,
+                order.channel_sat()
+            );
+
+            order.order.order_state = OrderState::Failed;
+            order.error = Some("Order expired without payment".to_string());
+            order.updated_at = now;
+
+            save_server_order(&mut self.client, order).await?;
+        }
+
+        Ok(())
+    }
+
+    // Confirmed outputs nothing else has reserved yet
+    async fn wallet_sat(&mut self) -> anyhow::Result<u64> {
+        let res = self
+            .client
+            .call(Request::ListFunds(ListfundsRequest { spent: None }))
+            .await?;
+
+        match res {
+            Response::ListFunds(n) => Ok(n
+                .outputs
+                .iter()
+                .filter(|output| {
+                    matches!(output.status, ListfundsOutputsStatus::CONFIRMED) && !output.reserved
+                })
+                .map(|output| output.amount_msat.msat() / 1000)
+                .sum()),
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+}
+
+// Show how much of our on-chain funds are promised to orders
+pub async fn lsps1_server_inventory(
+    p: Plugin<Arc<PluginState>>,
+    _v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let capital = Lsps1ServerInventory {
+        client: make_client(&p).await?,
+        plugin: p.clone(),
+    }
+    .capital()
+    .await?;
+
+    let orders = p.state().server_orders.lock().await;
+
+    let mut reservations: Vec<serde_json::Value> = orders
+        .values()
+        .filter(|order| {
+            order.order.order_state == OrderState::Created
+                && matches!(
+                    order.order.payment.state,
+                    PaymentState::ExpectPayment | PaymentState::Hold
+                )
+        })
+        .map(|order| {
+            json!({
+                "order_id": order.order.order_id,
+                "client": order.client.to_string(),
+                "amount_sat": order.channel_sat(),
+                "payment_state": order.order.payment.state,
+                "expires_at": order.expires_at,
+            })
+        })
+        .collect();
+
+    reservations.sort_by_key(|r| r["expires_at"].as_u64());
+
+    Ok(json!({
+        "wallet_sat": capital.wallet_sat,
+        "committed_sat": capital.committed_sat,
+        "in_flight_sat": capital.in_flight_sat,
+        "available_sat": capital.available_sat,
+        "reservations": reservations,
+    }))
+}
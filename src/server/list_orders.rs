@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use cln_plugin::{Error, Plugin};
+use serde_json::json;
+
+use crate::{constants::Lsps1ServerOrder, PluginState};
+
+// Show the orders clients placed with us and how we priced them
+pub async fn lsps1_server_orders(
+    p: Plugin<Arc<PluginState>>,
+    v: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let order_id = v["orderid"].as_str();
+    let client = v["client"].as_str();
+
+    let orders = p.state().server_orders.lock().await;
+
+    let mut orders: Vec<&Lsps1ServerOrder> = orders
+        .values()
+        .filter(|order| order_id.is_none_or(|id| order.order.order_id == id))
+        .filter(|order| client.is_none_or(|client| order.client.to_string() == client))
+        .collect();
+
+    orders.sort_by_key(|order| order.created_at);
+
+    let orders: Vec<serde_json::Value> = orders
+        .into_iter()
+        .map(|order| {
+            json!({
+                "order_id": order.order.order_id,
+                "client": order.client.to_string(),
+                "order_state": order.order.order_state,
+                "payment_state": order.order.payment.state,
+                "lsp_balance_sat": order.order.lsp_balance_sat,
+                "client_balance_sat": order.order.client_balance_sat,
+                "channel_expiry_blocks": order.order.channel_expiry_blocks,
+                "confirms_within_blocks": order.order.confirms_within_blocks,
+                "fee_total_sat": order.order.payment.fee_total_sat,
+                "order_total_sat": order.order.payment.order_total_sat,
+                "fee_breakdown": order.fee_breakdown,
+                "funding_outpoint": order
+                    .order
+                    .channel
+                    .as_ref()
+                    .map(|c| &c.funding_outpoint),
+                "error": order.error,
+                "created_at": order.created_at,
+                "updated_at": order.updated_at,
+            })
+        })
+        .collect();
+
+    Ok(json!({ "orders": orders }))
+}
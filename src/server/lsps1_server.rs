@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use cln_plugin::Plugin;
+use cln_rpc::primitives::PublicKey;
+use serde_json::json;
+
+use crate::{
+    client::utils::{make_client, send_message},
+    constants::{
+        CreateOrderJsonRpcRequestParams, GetOrderJsonRpcRequestParams, JsonRpcError,
+        JsonRpcRequest, ListProtocolsJsonRpcResponseResult, Lsps0ErrorCode,
+        LSPS0_LIST_PROTOCOLS_METHOD, LSPS1_CREATE_ORDER_METHOD, LSPS1_GET_INFO_METHOD,
+        LSPS1_GET_ORDER_METHOD, LSPS1_PROTOCOL,
+    },
+    PluginState,
+};
+
+use super::{
+    admission::Lsps1ServerAdmission, create_order::Lsps1ServerCreateOrder,
+    get_info::Lsps1ServerGetInfo, get_order::Lsps1ServerGetOrder, utils::internal_error,
+};
+
+// Answer a request from a peer buying liquidity from us
+pub async fn lsps1_server(
+    p: &Plugin<Arc<PluginState>>,
+    peer_id: &PublicKey,
+    request: JsonRpcRequest,
+) -> anyhow::Result<()> {
+    log::info!("Received {} request from {}", request.method, peer_id);
+
+    let result = handle_request(p, peer_id, &request).await;
+
+    let response = match result {
+        Ok(result) => json!({
+            "jsonrpc": "2.0",
+            "id": request.id,
+            "result": result,
+        }),
+        Err(error) => {
+            log::info!(
+                "Rejecting {} request from {}: {}",
+                request.method,
+                peer_id,
+                error.message
+            );
+
+            json!({
+                "jsonrpc": "2.0",
+                "id": request.id,
+                "error": error,
+            })
+        }
+    };
+
+    let mut client = make_client(p).await?;
+    send_message(&mut client, peer_id, &response).await
+}
+
+async fn handle_request(
+    p: &Plugin<Arc<PluginState>>,
+    peer_id: &PublicKey,
+    request: &JsonRpcRequest,
+) -> Result<serde_json::Value, JsonRpcError> {
+    match request.method.as_str() {
+        LSPS0_LIST_PROTOCOLS_METHOD => to_value(ListProtocolsJsonRpcResponseResult {
+            protocols: vec![LSPS1_PROTOCOL],
+        }),
+        LSPS1_GET_INFO_METHOD => to_value(Lsps1ServerGetInfo { plugin: p.clone() }.get_info()),
+        LSPS1_CREATE_ORDER_METHOD => {
+            let params: CreateOrderJsonRpcRequestParams = parse_params(&request.params)?;
+
+            Lsps1ServerAdmission {
+                client: make_client(p).await.map_err(internal_error)?,
+                peer_id: *peer_id,
+                plugin: p.clone(),
+            }
+            .admit()
+            .await?;
+
+            let order = Lsps1ServerCreateOrder {
+                client: make_client(p).await.map_err(internal_error)?,
+                peer_id: *peer_id,
+                params,
+                plugin: p.clone(),
+            }
+            .create_order()
+            .await?;
+
+            to_value(order)
+        }
+        LSPS1_GET_ORDER_METHOD => {
+            let params: GetOrderJsonRpcRequestParams = parse_params(&request.params)?;
+
+            let order = Lsps1ServerGetOrder {
+                client: make_client(p).await.map_err(internal_error)?,
+                peer_id: *peer_id,
+                order_id: params.order_id,
+                plugin: p.clone(),
+            }
+            .get_order()
+            .await?;
+
+            to_value(order)
+        }
+        _ => Err(JsonRpcError::new(
+            Lsps0ErrorCode::MethodNotFound,
+            "Method not found",
+        )),
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(
+    params: &serde_json::Value,
+) -> Result<T, JsonRpcError> {
+    serde_json::from_value(params.clone())
+        .map_err(|e| JsonRpcError::new(Lsps0ErrorCode::InvalidParams, &e.to_string()))
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Result<serde_json::Value, JsonRpcError> {
+    serde_json::to_value(value).map_err(|e| internal_error(e.into()))
+}
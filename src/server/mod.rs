@@ -0,0 +1,11 @@
+pub mod admission;
+pub mod create_order;
+pub mod get_info;
+pub mod get_order;
+pub mod hold_invoice;
+pub mod inventory;
+pub mod list_orders;
+pub mod lsps1_server;
+pub mod open_channel;
+pub mod pricing;
+pub mod utils;
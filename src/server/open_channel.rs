@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use cln_plugin::Plugin;
+use cln_rpc::{
+    model::requests::{FundchannelRequest, ListpeerchannelsRequest},
+    primitives::{Amount, AmountOrAll, ChannelSide, PublicKey},
+    ClnRpc, Request, Response,
+};
+
+use crate::{
+    client::utils::unix_timestamp,
+    constants::{CreateOrderJsonRpcResponseChannel, OrderState, PaymentState, BLOCK_TIME_SECS},
+    datastore::save_server_order,
+    PluginState,
+};
+
+use super::utils::iso8601;
+
+pub struct Lsps1ServerOpenChannel {
+    pub client: ClnRpc,
+    pub order_id: String,
+    pub plugin: Plugin<Arc<PluginState>>,
+}
+
+impl Lsps1ServerOpenChannel {
+    // Returns whether the channel opened, so the held payment can be settled or failed
+    pub async fn open_channel(&mut self) -> anyhow::Result<bool> {
+        // Mark the payment held first so it only gets opened once
+        let (client, lsp_balance_sat, client_balance_sat, announce, resuming) = {
+            let mut orders = self.plugin.state().server_orders.lock().await;
+
+            let order = match orders.get_mut(&self.order_id) {
+                Some(order) => order,
+                None => {
+                    log::warn!("Payment for unknown order {}", self.order_id);
+                    return Ok(false);
+                }
+            };
+
+            // Held already, so we restarted while opening the channel and the payment
+            // is being replayed, find out if the channel got opened
+            let resuming = match order.order.payment.state {
+                PaymentState::ExpectPayment => false,
+                PaymentState::Hold => true,
+                _ => return Ok(false),
+            };
+
+            if !resuming {
+                order.order.payment.state = PaymentState::Hold;
+                order.updated_at = unix_timestamp();
+
+                save_server_order(&mut self.client, order).await?;
+            }
+
+            (
+                order.client,
+                order.order.lsp_balance_sat.parse::<u64>()?,
+                order.order.client_balance_sat.parse::<u64>()?,
+                order.order.announce_channel,
+                resuming,
+            )
+        };
+
+        let res = if resuming {
+            log::info!(
+                "Payment for order {} was held before a restart, looking for its channel to {}",
+                self.order_id,
+                client
+            );
+
+            match self
+                .find_channel(&client, lsp_balance_sat + client_balance_sat)
+                .await
+            {
+                Ok(Some(funding_outpoint)) => Ok(funding_outpoint),
+                Ok(None) => Err(anyhow!("Channel was not opened before the restart")),
+                Err(e) => Err(e),
+            }
+        } else {
+            log::info!(
+                "Holding payment for order {}, opening channel to {}",
+                self.order_id,
+                client
+            );
+
+            self.fund_channel(&client, lsp_balance_sat, client_balance_sat, announce)
+                .await
+        };
+
+        let mut orders = self.plugin.state().server_orders.lock().await;
+
+        let order = match orders.get_mut(&self.order_id) {
+            Some(order) => order,
+            None => return Ok(false),
+        };
+
+        let now = unix_timestamp();
+        order.updated_at = now;
+
+        let opened = res.is_ok();
+
+        match res {
+            Ok(funding_outpoint) => {
+                log::info!(
+                    "Opened channel {} for order {}",
+                    funding_outpoint,
+                    self.order_id
+                );
+
+                order.order.order_state = OrderState::Completed;
+                order.order.payment.state = PaymentState::Paid;
+                order.order.channel = Some(CreateOrderJsonRpcResponseChannel {
+                    funded_at: iso8601(now),
+                    funding_outpoint,
+                    expires_at: iso8601(
+                        now + order.order.channel_expiry_blocks as u64 * BLOCK_TIME_SECS,
+                    ),
+                });
+            }
+            Err(e) => {
+                log::error!(
+                    "Opening channel for order {} failed, failing the payment back: {}",
+                    self.order_id,
+                    e
+                );
+
+                order.order.order_state = OrderState::Failed;
+                order.order.payment.state = PaymentState::Refunded;
+                order.error = Some(e.to_string());
+            }
+        }
+
+        save_server_order(&mut self.client, order).await?;
+
+        Ok(opened)
+    }
+
+    // A channel we opened to the client with the order's size that no other order claims
+    async fn find_channel(
+        &mut self,
+        client: &PublicKey,
+        channel_sat: u64,
+    ) -> anyhow::Result<Option<String>> {
+        let res = self
+            .client
+            .call(Request::ListPeerChannels(ListpeerchannelsRequest {
+                id: Some(*client),
+            }))
+            .await?;
+
+        let channels = match res {
+            Response::ListPeerChannels(n) => n.channels.unwrap_or_default(),
+            _ => {
+                bail!("Invalid response");
+            }
+        };
+
+        let claimed: Vec<String> = self
+            .plugin
+            .state()
+            .server_orders
+            .lock()
+            .await
+            .values()
+            .filter_map(|order| order.order.channel.as_ref())
+            .map(|channel| channel.funding_outpoint.clone())
+            .collect();
+
+        Ok(channels
+            .into_iter()
+            .filter(|c| matches!(c.opener, Some(ChannelSide::LOCAL)))
+            .filter(|c| {
+                c.total_msat
+                    .is_some_and(|total| total.msat() == channel_sat * 1000)
+            })
+            .filter_map(|c| Some(format!("{}:{}", c.funding_txid?, c.funding_outnum?)))
+            .find(|outpoint| !claimed.contains(outpoint)))
+    }
+
+    // Fund the whole channel and push the client balance to their side
+    async fn fund_channel(
+        &mut self,
+        client: &PublicKey,
+        lsp_balance_sat: u64,
+        client_balance_sat: u64,
+        announce: bool,
+    ) -> anyhow::Result<String> {
+        let res = self
+            .client
+            .call(Request::FundChannel(FundchannelRequest {
+                id: *client,
+                amount: AmountOrAll::Amount(Amount::from_sat(lsp_balance_sat + client_balance_sat)),
+                feerate: None,
+                announce: Some(announce),
+                minconf: None,
+                push_msat: Some(Amount::from_sat(client_balance_sat)),
+                close_to: None,
+                request_amt: None,
+                compact_lease: None,
+                utxos: None,
+                mindepth: None,
+                reserve: None,
+            }))
+            .await?;
+
+        match res {
+            Response::FundChannel(n) => Ok(format!("{}:{}", n.txid, n.outnum)),
+            _ => {
+                bail!("Invalid response");
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use anyhow::bail;
+use cln_plugin::Plugin;
+use cln_rpc::{
+    model::requests::{FeeratesRequest, FeeratesStyle},
+    ClnRpc, Request, Response,
+};
+
+use crate::{
+    constants::{
+        Lsps1ServerFeeBreakdown, LSPS1_SERVER_BASE_FEE_SAT, LSPS1_SERVER_BASE_FEE_SAT_OPTION,
+        LSPS1_SERVER_FAST_CONFIRM_BLOCKS, LSPS1_SERVER_FAST_CONFIRM_BLOCKS_OPTION,
+        LSPS1_SERVER_FAST_CONFIRM_FEE_SAT_OPTION, LSPS1_SERVER_FEE_PPM_OPTION,
+        LSPS1_SERVER_LEASE_FEE_PPB_OPTION,
+    },
+    PluginState,
+};
+
+use super::utils::option_u64;
+
+// How much we charge a client for a channel
+pub struct Lsps1ServerPricing {
+    pub base_fee_sat: u64,
+    pub fee_ppm: u64,
+    pub lease_fee_ppb: u64,
+    pub fast_confirm_blocks: u64,
+    pub fast_confirm_fee_sat: u64,
+}
+
+impl Lsps1ServerPricing {
+    pub fn from_options(plugin: &Plugin<Arc<PluginState>>) -> Self {
+        Self {
+            base_fee_sat: option_u64(
+                plugin,
+                LSPS1_SERVER_BASE_FEE_SAT_OPTION,
+                LSPS1_SERVER_BASE_FEE_SAT,
+            ),
+            fee_ppm: option_u64(plugin, LSPS1_SERVER_FEE_PPM_OPTION, 0),
+            lease_fee_ppb: option_u64(plugin, LSPS1_SERVER_LEASE_FEE_PPB_OPTION, 0),
+            fast_confirm_blocks: option_u64(
+                plugin,
+                LSPS1_SERVER_FAST_CONFIRM_BLOCKS_OPTION,
+                LSPS1_SERVER_FAST_CONFIRM_BLOCKS,
+            ),
+            fast_confirm_fee_sat: option_u64(plugin, LSPS1_SERVER_FAST_CONFIRM_FEE_SAT_OPTION, 0),
+        }
+    }
+
+    // Add up every part of the fee so we can show how we got to it later
+    pub async fn price(
+        &self,
+        client: &mut ClnRpc,
+        lsp_balance_sat: u64,
+        channel_expiry_blocks: u32,
+        confirms_within_blocks: u32,
+    ) -> anyhow::Result<Lsps1ServerFeeBreakdown> {
+        let (onchain_fee_sat, feerate_perkw) = Self::onchain_fee(client).await?;
+
+        self.breakdown(
+            lsp_balance_sat,
+            channel_expiry_blocks,
+            confirms_within_blocks,
+            onchain_fee_sat,
+            feerate_perkw,
+        )
+    }
+
+    // Big options can make these overflow, turn the order down instead
+    fn breakdown(
+        &self,
+        lsp_balance_sat: u64,
+        channel_expiry_blocks: u32,
+        confirms_within_blocks: u32,
+        onchain_fee_sat: u64,
+        feerate_perkw: Option<u32>,
+    ) -> anyhow::Result<Lsps1ServerFeeBreakdown> {
+        let ppm_fee_sat = match lsp_balance_sat.checked_mul(self.fee_ppm) {
+            Some(fee) => fee / 1_000_000,
+            None => bail!("Ppm fee overflows"),
+        };
+
+        // Our capital is locked up for the whole lease
+        let lease_fee_sat = match lsp_balance_sat
+            .checked_mul(self.lease_fee_ppb)
+            .and_then(|fee| fee.checked_mul(channel_expiry_blocks as u64))
+        {
+            Some(fee) => fee / 1_000_000_000,
+            None => bail!("Lease fee overflows"),
+        };
+
+        let fast_confirm_fee_sat = if (confirms_within_blocks as u64) < self.fast_confirm_blocks {
+            self.fast_confirm_fee_sat
+        } else {
+            0
+        };
+
+        let fee_total_sat = [
+            ppm_fee_sat,
+            lease_fee_sat,
+            onchain_fee_sat,
+            fast_confirm_fee_sat,
+        ]
+        .into_iter()
+        .try_fold(self.base_fee_sat, |total, fee| total.checked_add(fee));
+
+        let fee_total_sat = match fee_total_sat {
+            Some(fee_total_sat) => fee_total_sat,
+            None => bail!("Fee total overflows"),
+        };
+
+        Ok(Lsps1ServerFeeBreakdown {
+            base_fee_sat: self.base_fee_sat,
+            ppm_fee_sat,
+            lease_fee_sat,
+            onchain_fee_sat,
+            fast_confirm_fee_sat,
+            feerate_perkw,
+            fee_total_sat,
+        })
+    }
+
+    // What CLN thinks opening the channel will cost us right now
+    async fn onchain_fee(client: &mut ClnRpc) -> anyhow::Result<(u64, Option<u32>)> {
+        let res = client
+            .call(Request::Feerates(FeeratesRequest {
+                style: FeeratesStyle::PERKW,
+            }))
+            .await?;
+
+        let feerates = match res {
+            Response::Feerates(n) => n,
+            _ => {
+                bail!("Invalid response");
+            }
+        };
+
+        let feerate_perkw = feerates.perkw.and_then(|perkw| perkw.opening);
+
+        match feerates.onchain_fee_estimates {
+            Some(estimates) => Ok((estimates.opening_channel_satoshis, feerate_perkw)),
+            None => {
+                bail!("No on-chain fee estimates available")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pricing() -> Lsps1ServerPricing {
+        Lsps1ServerPricing {
+            base_fee_sat: 1000,
+            fee_ppm: 2000,
+            lease_fee_ppb: 10,
+            fast_confirm_blocks: 6,
+            fast_confirm_fee_sat: 500,
+        }
+    }
+
+    #[test]
+    fn price_adds_up_every_part() {
+        let fees = pricing()
+            .breakdown(1000000, 4320, 6, 2500, Some(1000))
+            .unwrap();
+
+        assert_eq!(fees.base_fee_sat, 1000);
+        // 2000 ppm of 1M sat
+        assert_eq!(fees.ppm_fee_sat, 2000);
+        // 10 ppb of 1M sat for 4320 blocks
+        assert_eq!(fees.lease_fee_sat, 43);
+        assert_eq!(fees.onchain_fee_sat, 2500);
+        assert_eq!(fees.fast_confirm_fee_sat, 0);
+        assert_eq!(fees.feerate_perkw, Some(1000));
+        assert_eq!(fees.fee_total_sat, 1000 + 2000 + 43 + 2500);
+    }
+
+    #[test]
+    fn price_charges_for_fast_confirmation() {
+        let fees = pricing().breakdown(1000000, 4320, 5, 2500, None).unwrap();
+
+        assert_eq!(fees.fast_confirm_fee_sat, 500);
+        assert_eq!(fees.fee_total_sat, 1000 + 2000 + 43 + 2500 + 500);
+    }
+
+    #[test]
+    fn price_rejects_overflowing_fees() {
+        let mut pricing = pricing();
+        pricing.lease_fee_ppb = u64::MAX / 1000;
+
+        assert!(pricing.breakdown(1000000, 4320, 6, 2500, None).is_err());
+
+        let mut pricing = self::pricing();
+        pricing.fee_ppm = u64::MAX;
+
+        assert!(pricing.breakdown(1000000, 4320, 6, 2500, None).is_err());
+
+        let mut pricing = self::pricing();
+        pricing.base_fee_sat = u64::MAX;
+
+        assert!(pricing.breakdown(1000000, 4320, 6, 2500, None).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use cln_plugin::{options::Value, Plugin};
+
+use crate::{
+    constants::{JsonRpcError, Lsps0ErrorCode, LSPS1_SERVER_OPTION},
+    PluginState,
+};
+
+pub fn server_enabled(plugin: &Plugin<Arc<PluginState>>) -> bool {
+    matches!(
+        plugin.option(LSPS1_SERVER_OPTION),
+        Some(Value::Boolean(true))
+    )
+}
+
+pub fn option_u64(plugin: &Plugin<Arc<PluginState>>, name: &str, default: u64) -> u64 {
+    match plugin.option(name) {
+        Some(Value::Integer(n)) if n >= 0 => n as u64,
+        _ => default,
+    }
+}
+
+// Log what went wrong but don't tell the client about our internals
+pub fn internal_error(e: anyhow::Error) -> JsonRpcError {
+    log::error!("LSPS1 server error: {}", e);
+
+    JsonRpcError::new(Lsps0ErrorCode::InternalError, "Internal error")
+}
+
+// LSPS1 dates are ISO 8601 strings in UTC
+pub fn iso8601(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iso8601_epoch() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn iso8601_leap_days() {
+        assert_eq!(iso8601(951782400), "2000-02-29T00:00:00.000Z");
+        assert_eq!(iso8601(1709251200 - 1), "2024-02-29T23:59:59.000Z");
+        assert_eq!(iso8601(1709251200), "2024-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn iso8601_century_without_leap_day() {
+        // 2100 is not a leap year, so the day before March 1st is February 28th
+        assert_eq!(iso8601(4107542400 - 86400), "2100-02-28T00:00:00.000Z");
+        assert_eq!(iso8601(4107542400), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn iso8601_end_of_year() {
+        assert_eq!(iso8601(946598400 + 86399), "1999-12-31T23:59:59.000Z");
+        assert_eq!(iso8601(946598400 + 86400), "2000-01-01T00:00:00.000Z");
+    }
+}
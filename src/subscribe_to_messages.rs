@@ -1,14 +1,24 @@
-use std::{path::Path, sync::Arc};
+use std::{str::FromStr, sync::Arc};
 
+use anyhow::{anyhow, bail};
 use cln_plugin::{Error, Plugin};
-use cln_rpc::ClnRpc;
+use cln_rpc::primitives::PublicKey;
 use serde_json::json;
 
 use crate::{
-    client::validate_and_pay::Lsps1ValidateAndPay,
+    client::{
+        pay_order::Lsps1PayOrder,
+        utils::{make_client, notify_order, unix_timestamp},
+        validate_and_pay::Lsps1ValidateAndPay,
+    },
     constants::{
-        CreateOrderJsonRpcResponse, GetInfoJsonRpcResponse, PluginMethodState, MESSAGE_TYPE,
+        CreateOrderJsonRpcResponse, GetInfoJsonRpcResponse, JsonRpcErrorResponse, JsonRpcRequest,
+        JsonRpcResponseId, ListProtocolsJsonRpcResponse, Lsps1PaymentStatus, PluginMethodState,
+        LSPS1_ORDER_CREATED_NOTIFICATION, LSPS1_ORDER_FAILED_NOTIFICATION,
+        LSPS1_ORDER_PAID_NOTIFICATION, MESSAGE_TYPE,
     },
+    datastore::save_order,
+    server::{lsps1_server::lsps1_server, utils::server_enabled},
     PluginState,
 };
 
@@ -18,9 +28,16 @@ pub async fn subscribe_to_custom_message(
 ) -> Result<serde_json::Value, Error> {
     let state_ref = p.state().clone();
 
-    // Now, you can lock the mutex asynchronously
-    let data = state_ref.data.lock().await;
-    let method = state_ref.method.lock().await;
+    let peer_id = match v
+        .get("peer_id")
+        .and_then(|v| v.as_str())
+        .and_then(|s| PublicKey::from_str(s).ok())
+    {
+        Some(peer_id) => peer_id,
+        None => {
+            return Ok(json!({ "result": "continue" }));
+        }
+    };
 
     // Attempt to extract "payload"
     let payload_hex = match v.get("payload").and_then(|v| v.as_str()) {
@@ -49,55 +66,352 @@ pub async fn subscribe_to_custom_message(
         return Ok(json!({ "result": "continue" }));
     }
 
-    let conf = p.configuration();
-    let socket_path = Path::new(&conf.lightning_dir).join(&conf.rpc_file);
-    let client = ClnRpc::new(socket_path).await?;
-
     // Extract the JSON payload starting from the 3rd byte
     let json_bytes = &bytes[2..];
 
-    // Get info response method
-    match serde_json::from_slice::<GetInfoJsonRpcResponse>(json_bytes) {
-        Ok(json_payload) => {
-            log::info!("GetInfo Response: {:?}", &json_payload)
+    // Requests come from peers buying from us, only answer them when we sell
+    if let Ok(request) = serde_json::from_slice::<JsonRpcRequest>(json_bytes) {
+        if server_enabled(&p) {
+            if let Err(e) = lsps1_server(&p, &peer_id, request).await {
+                log::error!("Failed to answer request from {}: {}", peer_id, e);
+            }
+        } else {
+            log::debug!(
+                "Ignoring {} request from {}, server mode is off",
+                request.method,
+                peer_id
+            );
+        }
+
+        return Ok(json!({ "result": "continue" }));
+    }
+
+    let id = match serde_json::from_slice::<JsonRpcResponseId>(json_bytes) {
+        Ok(response) => response.id,
+        _ => {
+            return Ok(json!({ "result": "continue" }));
+        }
+    };
+
+    // Only replies to requests we sent to this peer are handled
+    // A duplicate CreateOrder reply can still get past this as a late reply,
+    // validate_and_pay only lets the first one claim the order
+    let pending = state_ref
+        .pending
+        .lock()
+        .await
+        .remove(&(peer_id, id.clone()));
+
+    let (method, responder) = match pending {
+        Some(pending) => (pending.method, Some(pending.responder)),
+        None => {
+            if is_sent_to_other_peer(&state_ref, &peer_id, &id).await {
+                log::warn!(
+                    "SECURITY: Rejecting reply with id {} from {}, the request was sent to a different peer",
+                    id,
+                    peer_id
+                );
+                return Ok(json!({ "result": "continue" }));
+            }
+
+            // Nobody is waiting anymore, but a stored order that never got
+            // a reply can still be validated when it shows up late
+            if !is_awaiting_reply(&state_ref, &peer_id, &id).await {
+                log::debug!(
+                    "Ignoring unsolicited message with id {} from {}",
+                    id,
+                    peer_id
+                );
+                return Ok(json!({ "result": "continue" }));
+            }
+
+            log::info!("Received late CreateOrder reply with id {}", id);
+
+            (PluginMethodState::SendOrder, None)
         }
-        _ => {}
     };
 
-    // Create order/get order response method
-    match serde_json::from_slice::<CreateOrderJsonRpcResponse>(json_bytes) {
-        Ok(json_payload) => match *method {
-            PluginMethodState::GetOrder => {
-                log::info!("GetOrder Response: {:?}", &json_payload);
+    // The LSP might have answered with an error instead of a result
+    if let Ok(json_payload) = serde_json::from_slice::<JsonRpcErrorResponse>(json_bytes) {
+        log::error!(
+            "{} Error Response from {}: {:?}",
+            method,
+            peer_id,
+            &json_payload
+        );
+
+        if method == PluginMethodState::SendOrder {
+            if let Some(order) = state_ref.data.lock().await.get_mut(&id) {
+                order.error = Some(json_payload.error.clone());
+                order.updated_at = unix_timestamp();
+
+                // The caller still gets the LSP error if we can't store it
+                let saved = match make_client(&p).await {
+                    Ok(mut client) => save_order(&mut client, order).await,
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = saved {
+                    log::error!("Failed to store error for order {}: {}", id, e);
+                }
+
+                notify_order(&p, LSPS1_ORDER_FAILED_NOTIFICATION, order).await;
+            }
+        }
+
+        if let Some(responder) = responder {
+            let _ = responder.send(Err(anyhow!("{}", json_payload.error)));
+        }
+
+        return Ok(json!({ "result": "continue" }));
+    }
+
+    let response = match method {
+        PluginMethodState::ListProtocols => {
+            match serde_json::from_slice::<ListProtocolsJsonRpcResponse>(json_bytes) {
+                Ok(json_payload) => {
+                    log::info!("ListProtocols Response: {:?}", &json_payload);
+
+                    serde_json::to_value(&json_payload.result).map_err(|e| e.into())
+                }
+                Err(e) => Err(anyhow!("Invalid ListProtocols response: {}", e)),
+            }
+        }
+        PluginMethodState::GetInfo => {
+            match serde_json::from_slice::<GetInfoJsonRpcResponse>(json_bytes) {
+                Ok(json_payload) => {
+                    log::info!("GetInfo Response: {:?}", &json_payload);
+
+                    serde_json::to_value(&json_payload.result).map_err(|e| e.into())
+                }
+                Err(e) => Err(anyhow!("Invalid GetInfo response: {}", e)),
+            }
+        }
+        PluginMethodState::GetOrder => {
+            match serde_json::from_slice::<CreateOrderJsonRpcResponse>(json_bytes) {
+                Ok(json_payload) => {
+                    log::info!("GetOrder Response: {:?}", &json_payload);
+
+                    serde_json::to_value(&json_payload.result).map_err(|e| e.into())
+                }
+                Err(e) => Err(anyhow!("Invalid GetOrder response: {}", e)),
             }
-            PluginMethodState::SendOrder => {
-                log::info!("CreateOrder Response: {:?}", &json_payload);
-
-                let get_order = data.get(&json_payload.id);
-
-                if let Some(order) = get_order {
-                    let res = Lsps1ValidateAndPay {
-                        order: order.to_string(),
-                        client,
-                        order_response_payload: json_payload,
-                    }
-                    .validate_and_pay()
-                    .await;
-
-                    match res {
-                        Ok(_) => {
-                            log::info!("Order validated and paid");
-                        }
-                        Err(e) => {
-                            log::error!("Order validation and payment failed: {}", e);
-                        }
-                    }
+        }
+        PluginMethodState::SendOrder => {
+            match serde_json::from_slice::<CreateOrderJsonRpcResponse>(json_bytes) {
+                Ok(json_payload) => {
+                    log::info!("CreateOrder Response: {:?}", &json_payload);
+
+                    validate_and_pay(&p, &peer_id, json_payload).await
                 }
+                Err(e) => Err(anyhow!("Invalid CreateOrder response: {}", e)),
             }
-            _ => {}
-        },
-        _ => {}
+        }
     };
 
-    return Ok(json!({ "result": "continue" }));
+    // The requester might have timed out already
+    if let Some(responder) = responder {
+        let _ = responder.send(response);
+    }
+
+    Ok(json!({ "result": "continue" }))
+}
+
+// Check if a request with this id went out to some other peer
+async fn is_sent_to_other_peer(state: &PluginState, peer_id: &PublicKey, id: &str) -> bool {
+    let pending = state.pending.lock().await;
+
+    if pending
+        .keys()
+        .any(|(pubkey, pending_id)| pending_id == id && pubkey != peer_id)
+    {
+        return true;
+    }
+
+    let data = state.data.lock().await;
+
+    matches!(data.get(id), Some(order) if &order.lsp != peer_id)
+}
+
+// Check if this is an order we sent to this peer that never got a reply
+async fn is_awaiting_reply(state: &PluginState, peer_id: &PublicKey, id: &str) -> bool {
+    let data = state.data.lock().await;
+
+    matches!(
+        data.get(id),
+        Some(order) if &order.lsp == peer_id && order.response.is_none() && order.error.is_none()
+    )
+}
+
+async fn validate_and_pay(
+    p: &Plugin<Arc<PluginState>>,
+    peer_id: &PublicKey,
+    json_payload: CreateOrderJsonRpcResponse,
+) -> anyhow::Result<serde_json::Value> {
+    let state_ref = p.state().clone();
+    let mut client = make_client(p).await?;
+
+    let id = json_payload.id.clone();
+    let result = json_payload.result.clone();
+
+    // Store the reply, then let go of the orders so paying doesn't block everything else
+    let (request, max_fee_sat, payment_method, feerate, min_onchain_payment_sat, dry_run) = {
+        let mut data = state_ref.data.lock().await;
+
+        let order = match data.get_mut(&id) {
+            Some(order) => order,
+            None => {
+                bail!("No order found for id {}", id)
+            }
+        };
+
+        // Never pay an order on behalf of a peer it wasn't sent to
+        if &order.lsp != peer_id {
+            log::warn!(
+                "SECURITY: Order {} was sent to {} but the reply came from {}",
+                id,
+                order.lsp,
+                peer_id
+            );
+            bail!("Order reply came from the wrong peer");
+        }
+
+        // Claim the order, so a duplicate reply can't pay it again
+        if order.response.is_some() || order.payment_status != Lsps1PaymentStatus::Pending {
+            log::warn!(
+                "Ignoring duplicate reply for order {}, payment status is {:?}",
+                id,
+                order.payment_status
+            );
+            bail!("Order {} already got a reply", id);
+        }
+
+        order.response = Some(result.clone());
+        order.updated_at = unix_timestamp();
+
+        save_order(&mut client, order).await?;
+        notify_order(p, LSPS1_ORDER_CREATED_NOTIFICATION, order).await;
+
+        (
+            order.request.clone(),
+            order.max_fee_sat,
+            order.payment_method,
+            order.feerate.clone(),
+            order.min_onchain_payment_sat,
+            order.dry_run,
+        )
+    };
+
+    let mut validator = Lsps1ValidateAndPay {
+        order: request,
+        max_fee_sat,
+        payment_method,
+        feerate,
+        min_onchain_payment_sat,
+        network: p.configuration().network,
+        client,
+        order_response_payload: json_payload,
+    };
+
+    // Dry runs only report what we would have paid
+    if dry_run {
+        let quote = validator.quote().await;
+
+        if let Some(order) = state_ref.data.lock().await.get_mut(&id) {
+            order.payment_status = Lsps1PaymentStatus::DryRun;
+            order.payment_error = quote.error.clone();
+            order.updated_at = unix_timestamp();
+
+            save_order(&mut validator.client, order).await?;
+        }
+
+        log::info!("Dry run quote: {:?}", quote);
+
+        return Ok(serde_json::to_value(&quote)?);
+    }
+
+    // Big orders wait for the user to confirm them with payorder
+    let order_total_sat: u64 = result.payment.order_total_sat.parse()?;
+
+    if Lsps1PayOrder::needs_confirmation(p, order_total_sat) {
+        let res = validator.validate().await;
+
+        if let Some(order) = state_ref.data.lock().await.get_mut(&id) {
+            order.updated_at = unix_timestamp();
+
+            match &res {
+                Ok(_) => {
+                    order.payment_status = Lsps1PaymentStatus::AwaitingConfirmation;
+                }
+                Err(e) => {
+                    order.payment_status = Lsps1PaymentStatus::Failed;
+                    order.payment_error = Some(e.to_string());
+                }
+            }
+
+            save_order(&mut validator.client, order).await?;
+
+            if res.is_err() {
+                notify_order(p, LSPS1_ORDER_FAILED_NOTIFICATION, order).await;
+            }
+        }
+
+        return match res {
+            Ok(_) => {
+                log::info!("Order {} is awaiting confirmation", result.order_id);
+
+                let mut value = serde_json::to_value(&result)?;
+                value["payment_status"] =
+                    serde_json::to_value(Lsps1PaymentStatus::AwaitingConfirmation)?;
+
+                Ok(value)
+            }
+            Err(e) => {
+                log::error!("Order validation failed: {}", e);
+
+                bail!("Order {} validation failed: {}", result.order_id, e)
+            }
+        };
+    }
+
+    let res = validator.validate_and_pay().await;
+
+    if let Some(order) = state_ref.data.lock().await.get_mut(&id) {
+        order.updated_at = unix_timestamp();
+
+        match &res {
+            Ok(txid) => {
+                order.payment_status = Lsps1PaymentStatus::Paid;
+                order.onchain_txid = txid.clone();
+            }
+            Err(e) => {
+                order.payment_status = Lsps1PaymentStatus::Failed;
+                order.payment_error = Some(e.to_string());
+            }
+        }
+
+        save_order(&mut validator.client, order).await?;
+
+        match &res {
+            Ok(_) => notify_order(p, LSPS1_ORDER_PAID_NOTIFICATION, order).await,
+            Err(_) => notify_order(p, LSPS1_ORDER_FAILED_NOTIFICATION, order).await,
+        }
+    }
+
+    match res {
+        Ok(_) => {
+            log::info!("Order validated and paid");
+
+            Ok(serde_json::to_value(&result)?)
+        }
+        Err(e) => {
+            log::error!("Order validation and payment failed: {}", e);
+
+            bail!(
+                "Order {} validation and payment failed: {}",
+                result.order_id,
+                e
+            )
+        }
+    }
 }